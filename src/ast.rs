@@ -1,5 +1,10 @@
 use std::cmp::{min, max};
+use std::fmt;
+use std::fmt::Display;
+use std::mem;
 use errors::{CalcrResult, CalcrError};
+use lexer::LexerOptions;
+use output::OutputFormat;
 
 #[derive(Debug, PartialEq)]
 pub struct Ast {
@@ -35,32 +40,224 @@ impl Ast {
         }
     }
 
-    pub fn get_total_span(&self) -> (usize, usize) {
-        if self.is_leaf() {
-            self.span
+    /// Returns how tightly this node binds its operands, used to decide where parentheses are
+    /// needed when displaying it
+    fn precedence(&self) -> u32 {
+        match self.val {
+            AstVal::Op(OpKind::Assign) => 0,
+            AstVal::Op(OpKind::Or) => 1,
+            AstVal::Op(OpKind::And) => 2,
+            AstVal::Op(ref op) if op.is_comparison() => 3,
+            AstVal::Op(OpKind::Xor) => 4,
+            AstVal::Op(OpKind::Plus) | AstVal::Op(OpKind::Minus) => 5,
+            AstVal::Op(OpKind::Mult) | AstVal::Op(OpKind::Div) |
+            AstVal::Op(OpKind::Mod) | AstVal::Op(OpKind::IntDiv) | AstVal::Op(OpKind::Of) => 6,
+            AstVal::Op(OpKind::Neg) => 7,
+            AstVal::Num(num) if num < 0.0 => 7,
+            AstVal::Op(OpKind::Pow) => 8,
+            AstVal::Op(OpKind::Fact) | AstVal::Op(OpKind::Deg) => 9,
+            _ => 10,
+        }
+    }
+
+    /// Writes `branch`, surrounded by parentheses if it binds looser than `min_precedence`
+    fn fmt_branch(&self, f: &mut fmt::Formatter, branch: &Ast, min_precedence: u32,
+                  options: LexerOptions) -> fmt::Result {
+        if branch.precedence() < min_precedence {
+            write!(f, "({})", branch.written(options))
         } else {
-            self.branches.iter()
-                         .map(|br| br.get_total_span())
-                         .fold(self.span, |out, span| (min(out.0, span.0), max(out.1, span.1)))
+            write!(f, "{}", branch.written(options))
+        }
+    }
+
+    /// Returns the expression written such that the lexer reads it back with `options`, e.g.
+    /// with powers written as `**` when `^` is exclusive or
+    pub fn written(&self, options: LexerOptions) -> Written<'_> {
+        Written { ast: self, options: options }
+    }
+
+    pub fn get_total_span(&self) -> (usize, usize) {
+        // like when dropping, recursing could overflow the stack for very deep trees
+        let mut span = self.span;
+        let mut stack: Vec<&Ast> = self.branches.iter().collect();
+        while let Some(ast) = stack.pop() {
+            span = (min(span.0, ast.span.0), max(span.1, ast.span.1));
+            stack.extend(ast.branches.iter());
         }
+        span
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl Drop for Ast {
+    fn drop(&mut self) {
+        // Dropping the branches recursively could overflow the stack for very deep trees, such as
+        // the one resulting from a long chain of additions, so we flatten the tree first.
+        let mut stack = mem::replace(&mut self.branches, Vec::new());
+        while let Some(mut ast) = stack.pop() {
+            stack.extend(ast.branches.drain(..));
+        }
+    }
+}
+
+impl Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.written(LexerOptions::new()))
+    }
+}
+
+/// An `Ast` written for particular `LexerOptions`, as returned by `Ast::written`
+pub struct Written<'a> {
+    ast: &'a Ast,
+    options: LexerOptions,
+}
+
+impl<'a> Display for Written<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (ast, options) = (self.ast, self.options);
+        let prec = ast.precedence();
+        match (&ast.val, &ast.branches[..]) {
+            (&AstVal::Op(OpKind::Neg), [ref child]) => {
+                try!(write!(f, "-"));
+                ast.fmt_branch(f, child, prec + 1, options)
+            },
+            (&AstVal::Op(ref op @ OpKind::Fact), [ref child]) |
+            (&AstVal::Op(ref op @ OpKind::Deg), [ref child]) => {
+                try!(ast.fmt_branch(f, child, prec + 1, options));
+                write!(f, "{}", op.symbol())
+            },
+            (&AstVal::Op(ref op), [ref lhs, ref rhs]) => {
+                // all operators are left associative except powers
+                let (lhs_prec, rhs_prec) = if *op == OpKind::Pow {
+                    (prec + 1, prec)
+                } else {
+                    (prec, prec + 1)
+                };
+                try!(ast.fmt_branch(f, lhs, lhs_prec, options));
+                if *op == OpKind::Pow && options.caret_xor {
+                    try!(write!(f, " ** "));
+                } else {
+                    try!(write!(f, " {} ", op.symbol()));
+                }
+                ast.fmt_branch(f, rhs, rhs_prec, options)
+            },
+            (&AstVal::Op(OpKind::Assign), branches) if branches.len() > 2 => {
+                // an assignment to several variables, with the names followed by the values
+                let (names, values) = branches.split_at(branches.len() / 2);
+                try!(fmt_list(f, names, options));
+                try!(write!(f, " = "));
+                fmt_list(f, values, options)
+            },
+            (&AstVal::Func(FuncKind::Log), [_, ref base]) if is_whole_num(base) => {
+                fmt_call(f, &format!("log_{}", base), &ast.branches[..1], options)
+            },
+            (&AstVal::Func(ref func), args) => fmt_call(f, func.name(), args, options),
+            (&AstVal::Call(ref name), args) => fmt_call(f, name, args, options),
+            (&AstVal::Const(ref c), _) => write!(f, "{}", c.name()),
+            (&AstVal::Num(num), _) => {
+                let mut format = OutputFormat::new();
+                format.full_precision = true;
+                format.decimal_comma = options.decimal_comma;
+                write!(f, "{}", format.format(num))
+            },
+            (&AstVal::LastResult, _) => write!(f, "ans"),
+            (&AstVal::PrevResult(n), _) => write!(f, "prev{}", n),
+            (&AstVal::Name(ref name), _) | (&AstVal::CalledName(ref name), _) => {
+                write!(f, "{}", name)
+            },
+            (&AstVal::Op(_), _) => write!(f, "<invalid operator>"),
+        }
+    }
+}
+
+/// Whether `ast` is a number which can be written as the subscript of `log_`
+fn is_whole_num(ast: &Ast) -> bool {
+    match ast.val {
+        AstVal::Num(num) => num >= 0.0 && num.fract() == 0.0,
+        _ => false,
+    }
+}
+
+/// Writes a call to the function `name` with the arguments `args`, e.g. `sin(x)`
+fn fmt_call(f: &mut fmt::Formatter, name: &str, args: &[Ast], options: LexerOptions)
+            -> fmt::Result {
+    try!(write!(f, "{}(", name));
+    try!(fmt_list(f, args, options));
+    write!(f, ")")
+}
+
+/// Writes `asts` separated by commas, e.g. `1, x + 2`, or by semicolons when using decimal
+/// commas
+fn fmt_list(f: &mut fmt::Formatter, asts: &[Ast], options: LexerOptions) -> fmt::Result {
+    for (i, ast) in asts.iter().enumerate() {
+        if i > 0 {
+            try!(write!(f, "{} ", if options.decimal_comma { ';' } else { ',' }));
+        }
+        try!(write!(f, "{}", ast.written(options)));
+    }
+    Ok(())
+}
+
+/// A pass over an `Ast`, where each method is called by `walk` for the nodes of that kind
+///
+/// All methods do nothing by default, so a visitor only needs to implement the ones it cares
+/// about.
+pub trait AstVisitor {
+    fn visit_func(&mut self, _func: &FuncKind, _ast: &Ast) {}
+    fn visit_op(&mut self, _op: &OpKind, _ast: &Ast) {}
+    fn visit_const(&mut self, _c: &ConstKind, _ast: &Ast) {}
+    fn visit_num(&mut self, _num: f64, _ast: &Ast) {}
+    fn visit_last_result(&mut self, _ast: &Ast) {}
+    fn visit_prev_result(&mut self, _back: usize, _ast: &Ast) {}
+    fn visit_name(&mut self, _name: &str, _ast: &Ast) {}
+    fn visit_call(&mut self, _name: &str, _ast: &Ast) {}
+}
+
+/// Visits every node of `ast` with `visitor`, each node before its branches from left to right
+pub fn walk<V: AstVisitor>(visitor: &mut V, ast: &Ast) {
+    // like when dropping, recursing could overflow the stack for very deep trees
+    let mut stack = vec!(ast);
+    while let Some(ast) = stack.pop() {
+        match ast.val {
+            AstVal::Func(ref func) => visitor.visit_func(func, ast),
+            AstVal::Op(ref op) => visitor.visit_op(op, ast),
+            AstVal::Const(ref c) => visitor.visit_const(c, ast),
+            AstVal::Num(num) => visitor.visit_num(num, ast),
+            AstVal::LastResult => visitor.visit_last_result(ast),
+            AstVal::PrevResult(back) => visitor.visit_prev_result(back, ast),
+            AstVal::Name(ref name) | AstVal::CalledName(ref name) => visitor.visit_name(name, ast),
+            AstVal::Call(ref name) => visitor.visit_call(name, ast),
+        }
+        stack.extend(ast.branches.iter().rev());
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum AstVal {
     Func(FuncKind),
     Op(OpKind),
     Const(ConstKind),
     Num(f64),
     LastResult,
+    /// The result this many expressions back in the current batch
+    PrevResult(usize),
     Name(String),
+    /// A name in function position which is not a known function, e.g. `f` in `f(2)`
+    ///
+    /// It is multiplied by what follows like a variable, if there is one, and is otherwise
+    /// reported as an unknown function rather than an undefined variable.
+    CalledName(String),
+    /// A call to a function registered with `Interpreter::register_fn`
+    Call(String),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum FuncKind {
     Sin,
     Cos,
     Tan,
+    Sec,
+    Csc,
+    Cot,
     Asin,
     Acos,
     Atan,
@@ -69,6 +266,53 @@ pub enum FuncKind {
     Exp,
     Ln,
     Log,
+    Stddev,
+    Variance,
+    Frac,
+    IntPart,
+    Floor,
+    Ceil,
+    Gamma,
+    Hypot,
+    Dist,
+    Snap,
+    ToBase,
+    Solve,
+    ContFrac,
+}
+
+impl FuncKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FuncKind::Sin => "sin",
+            FuncKind::Cos => "cos",
+            FuncKind::Tan => "tan",
+            FuncKind::Sec => "sec",
+            FuncKind::Csc => "csc",
+            FuncKind::Cot => "cot",
+            FuncKind::Asin => "asin",
+            FuncKind::Acos => "acos",
+            FuncKind::Atan => "atan",
+            FuncKind::Sqrt => "sqrt",
+            FuncKind::Abs => "abs",
+            FuncKind::Exp => "exp",
+            FuncKind::Ln => "ln",
+            FuncKind::Log => "log",
+            FuncKind::Stddev => "stddev",
+            FuncKind::Variance => "variance",
+            FuncKind::Frac => "frac",
+            FuncKind::IntPart => "int",
+            FuncKind::Floor => "floor",
+            FuncKind::Ceil => "ceil",
+            FuncKind::Gamma => "gamma",
+            FuncKind::Hypot => "hypot",
+            FuncKind::Dist => "dist",
+            FuncKind::Snap => "snap",
+            FuncKind::ToBase => "tobase",
+            FuncKind::Solve => "solve",
+            FuncKind::ContFrac => "cf",
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -77,15 +321,137 @@ pub enum OpKind {
     Minus,
     Mult,
     Div,
+    Mod,
+    IntDiv,
+    /// Multiplication written as a word, e.g. `0.2 of 50`
+    Of,
     Pow,
     Fact,
+    Deg,
     Neg,
     Assign,
+    And,
+    Or,
+    Xor,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+    Equal,
+    NotEqual,
 }
 
-#[derive(Debug, PartialEq)]
+impl OpKind {
+    pub fn is_comparison(&self) -> bool {
+        matches!(*self, OpKind::Less | OpKind::Greater | OpKind::LessEq | OpKind::GreaterEq |
+                        OpKind::Equal | OpKind::NotEqual)
+    }
+
+    /// Whether the operator results in a truth value, i.e. 1 for true and 0 for false
+    pub fn is_boolean(&self) -> bool {
+        *self == OpKind::And || *self == OpKind::Or || self.is_comparison()
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match *self {
+            OpKind::Plus => "+",
+            OpKind::Minus | OpKind::Neg => "-",
+            OpKind::Mult => "*",
+            OpKind::Div => "/",
+            OpKind::Mod => "mod",
+            OpKind::IntDiv => "div",
+            OpKind::Of => "of",
+            OpKind::Pow => "^",
+            OpKind::Fact => "!",
+            OpKind::Deg => "°",
+            OpKind::Assign => "=",
+            OpKind::And => "&&",
+            OpKind::Or => "||",
+            OpKind::Xor => "^",
+            OpKind::Less => "<",
+            OpKind::Greater => ">",
+            OpKind::LessEq => "<=",
+            OpKind::GreaterEq => ">=",
+            OpKind::Equal => "==",
+            OpKind::NotEqual => "!=",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum ConstKind {
     Pi,
     E,
     Phi,
+    Inf,
+    NaN,
+}
+
+impl ConstKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ConstKind::Pi => "pi",
+            ConstKind::E => "e",
+            ConstKind::Phi => "phi",
+            ConstKind::Inf => "inf",
+            ConstKind::NaN => "nan",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ast, AstVal, OpKind, AstVisitor, walk};
+
+    fn num(num: f64) -> Ast {
+        Ast { val: AstVal::Num(num), span: (0, 0), branches: vec!() }
+    }
+
+    fn op(op: OpKind, branches: Vec<Ast>) -> Ast {
+        Ast { val: AstVal::Op(op), span: (0, 0), branches: branches }
+    }
+
+    #[test]
+    fn display_precedence() {
+        let ast = op(OpKind::Mult, vec!(op(OpKind::Plus, vec!(num(1.0), num(2.0))), num(3.0)));
+        assert_eq!(ast.to_string(), "(1 + 2) * 3");
+        let ast = op(OpKind::Plus, vec!(num(1.0), op(OpKind::Mult, vec!(num(2.0), num(3.0)))));
+        assert_eq!(ast.to_string(), "1 + 2 * 3");
+    }
+
+    /// Counts the numbers and operators of an `Ast`, noting the order of the numbers
+    struct Counter {
+        nums: Vec<f64>,
+        ops: usize,
+    }
+
+    impl AstVisitor for Counter {
+        fn visit_num(&mut self, num: f64, _ast: &Ast) {
+            self.nums.push(num);
+        }
+
+        fn visit_op(&mut self, _op: &OpKind, _ast: &Ast) {
+            self.ops += 1;
+        }
+    }
+
+    #[test]
+    fn visitor() {
+        let ast = op(OpKind::Plus, vec!(op(OpKind::Mult, vec!(num(1.0), num(2.0))),
+                                        op(OpKind::Neg, vec!(num(3.0)))));
+        let mut counter = Counter { nums: vec!(), ops: 0 };
+        walk(&mut counter, &ast);
+        assert_eq!(counter.nums, vec!(1.0, 2.0, 3.0));
+        assert_eq!(counter.ops, 3);
+    }
+
+    #[test]
+    fn display_associativity() {
+        let ast = op(OpKind::Minus, vec!(num(1.0), op(OpKind::Minus, vec!(num(2.0), num(3.0)))));
+        assert_eq!(ast.to_string(), "1 - (2 - 3)");
+        let ast = op(OpKind::Pow, vec!(op(OpKind::Pow, vec!(num(2.0), num(3.0))), num(4.0)));
+        assert_eq!(ast.to_string(), "(2 ^ 3) ^ 4");
+        let ast = op(OpKind::Pow, vec!(num(-2.0), num(2.0)));
+        assert_eq!(ast.to_string(), "(-2) ^ 2");
+    }
 }
\ No newline at end of file
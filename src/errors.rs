@@ -1,10 +1,14 @@
 use std::fmt;
+use std::cmp::min;
 use std::fmt::Display;
-use unicode_width::UnicodeWidthChar;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 use std::error::Error;
 
 pub type CalcrResult<T> = Result<T, CalcrError>;
 
+/// Shown in place of characters which can't be printed, such as control characters
+pub const PLACEHOLDER: char = '·';
+
 #[derive(Debug, PartialEq)]
 pub struct CalcrError {
     pub desc: String,
@@ -12,31 +16,72 @@ pub struct CalcrError {
 }
 
 impl CalcrError {
-    pub fn print_location_highlight(&self, input: &String, print_input: bool) {
-        let (begin, end) = self.span.unwrap_or((0, input.chars().count()));
-        if print_input {
-            println!("  {}", input);
-            print!("  ");
-        } else {
-            print!("   ");
-        }
-        for _ in 0..begin {
-            print!(" ");
+    /// Returns the characters of `input` covered by the span, or `None` if there is no span
+    pub fn snippet(&self, input: &str) -> Option<String> {
+        self.span.map(|(begin, end)| input.chars().skip(begin).take(end - begin).collect())
+    }
+
+    /// Prints a line marking the location of the error in `input`
+    ///
+    /// If `prompt` is given, `input` is assumed to already be shown right after it on the line
+    /// above. Otherwise `input` is printed first, indented by two spaces.
+    pub fn print_location_highlight(&self, input: &String, prompt: Option<&str>) {
+        let offset = match prompt {
+            Some(prompt) => prompt.width(),
+            None => {
+                println!("  {}", printable(input));
+                2
+            },
+        };
+        println!("{}", self.location_highlight(input, offset));
+    }
+
+    /// Describes the error as `line:col: desc`, where `text` is the line numbered `line` of a
+    /// file, and the column counts characters from 1
+    pub fn at_line(&self, line: usize, text: &str) -> String {
+        match self.span {
+            Some((begin, _)) => {
+                let col = min(begin, text.chars().count()) + 1;
+                format!("{}:{}: {}", line, col, self.desc)
+            },
+            None => format!("{}: {}", line, self.desc),
         }
-        print!("^");
+    }
+
+    /// Returns a line marking the span of the error in `input`, such as `  ^~~`, where `input`
+    /// starts `offset` columns into its line
+    pub fn location_highlight(&self, input: &str, offset: usize) -> String {
+        let (begin, end) = self.span.unwrap_or((0, input.chars().count()));
         // Since the span is in characters, and that number does not necessarily correspond with
         // how many bytes OR display columns we need, the only way to get the number of columns
         // is by looping over the characters and summing the widths.
-        for _ in 1..input.chars()
-                         .skip(begin)
-                         .take(end-begin)
-                         .fold(0, |len, ch| len + ch.width().unwrap_or(0)) {
-            print!("~");
+        let indent = offset + columns(input.chars().take(begin));
+        let len = columns(input.chars().skip(begin).take(end - begin));
+        let mut out = " ".repeat(indent);
+        out.push('^');
+        for _ in 1..len {
+            out.push('~');
         }
-        println!("");
+        out
     }
 }
 
+/// Returns `input` as it should be echoed, with each non-printable character replaced by
+/// `PLACEHOLDER`, so every character takes up the columns given by `char_width`
+pub fn printable(input: &str) -> String {
+    input.chars().map(|ch| if ch.width().is_some() { ch } else { PLACEHOLDER }).collect()
+}
+
+/// Returns how many columns `ch` takes up when echoed with `printable`
+pub fn char_width(ch: char) -> usize {
+    ch.width().unwrap_or(1)
+}
+
+/// Returns how many columns `chars` take up when echoed with `printable`
+pub fn columns<I: Iterator<Item=char>>(chars: I) -> usize {
+    chars.fold(0, |len, ch| len + char_width(ch))
+}
+
 impl Error for CalcrError {
     fn description(&self) -> &str {
         self.desc.as_ref()
@@ -48,7 +93,92 @@ impl Error for CalcrError {
 }
 
 impl Display for CalcrError {
+    /// Writes the description, followed by the span when using the alternate flag, e.g.
+    /// `Invalid char: ? (at 2..3)` for `{:#}`
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.desc)
+        match self.span {
+            Some((begin, end)) if f.alternate() => {
+                write!(f, "{} (at {}..{})", self.desc, begin, end)
+            },
+            _ => write!(f, "{}", self.desc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CalcrError, printable};
+
+    #[test]
+    fn location_highlight() {
+        let err = CalcrError {
+            desc: "Invalid char: ?".to_string(),
+            span: Some((4, 7)),
+        };
+        assert_eq!(err.location_highlight("1 + abc", 2), "      ^~~");
+        // e.g. after the prompt `rad>> `
+        assert_eq!(err.location_highlight("1 + abc", 6), "          ^~~");
+        // wide chars take up two columns each
+        let err = CalcrError {
+            desc: "Undefined variable: 日本".to_string(),
+            span: Some((5, 7)),
+        };
+        assert_eq!(err.location_highlight("日本 + 日本", 0), "       ^~~~");
+        let err = CalcrError {
+            desc: "Nothing to undo".to_string(),
+            span: None,
+        };
+        assert_eq!(err.location_highlight("undo", 3), "   ^~~~");
+    }
+
+    #[test]
+    fn control_chars() {
+        let input = "1 +\u{7} x";
+        assert_eq!(printable(input), "1 +· x");
+        let err = CalcrError {
+            desc: "Invalid char: \u{7}".to_string(),
+            span: Some((3, 4)),
+        };
+        assert_eq!(err.location_highlight(input, 2), "     ^");
+        let err = CalcrError {
+            desc: "Undefined variable: x".to_string(),
+            span: Some((5, 6)),
+        };
+        assert_eq!(err.location_highlight(input, 2), "       ^");
+    }
+
+    #[test]
+    fn at_line() {
+        let err = CalcrError {
+            desc: "Undefined variable: x".to_string(),
+            span: Some((4, 5)),
+        };
+        assert_eq!(err.at_line(2, "1 + x"), "2:5: Undefined variable: x");
+        // the end of the line is the last column an error can be at
+        let err = CalcrError {
+            desc: "Expected number or constant".to_string(),
+            span: Some((9, 9)),
+        };
+        assert_eq!(err.at_line(3, "1 +"), "3:4: Expected number or constant");
+        let err = CalcrError {
+            desc: "Nothing to undo".to_string(),
+            span: None,
+        };
+        assert_eq!(err.at_line(1, "undo"), "1: Nothing to undo");
+    }
+
+    #[test]
+    fn display() {
+        let err = CalcrError {
+            desc: "Invalid char: ?".to_string(),
+            span: Some((2, 3)),
+        };
+        assert_eq!(format!("{}", err), "Invalid char: ?");
+        assert_eq!(format!("{:#}", err), "Invalid char: ? (at 2..3)");
+        let err = CalcrError {
+            desc: "Nothing to undo".to_string(),
+            span: None,
+        };
+        assert_eq!(format!("{:#}", err), "Nothing to undo");
     }
 }
@@ -4,11 +4,15 @@ use super::CMD_PROMPT;
 use super::{InputHandler, InputCmd};
 use super::Key;
 
-pub struct DefaultInputHandler;
+pub struct DefaultInputHandler {
+    prompt: String,
+}
 
 impl DefaultInputHandler {
     pub fn new() -> DefaultInputHandler {
-        DefaultInputHandler
+        DefaultInputHandler {
+            prompt: CMD_PROMPT.to_string(),
+        }
     }
 }
 
@@ -25,8 +29,9 @@ impl InputHandler for DefaultInputHandler {
 
     fn handle_input(&mut self) -> InputCmd {
         let mut cmd = String::new();
-        if let Ok(_) = io::stdin().read_line(&mut cmd) {
-            if cmd.trim() == "quit" || cmd.trim() == "exit" {
+        if let Ok(read) = io::stdin().read_line(&mut cmd) {
+            // nothing was read, so we have reached the end of the input
+            if read == 0 || cmd.trim() == "quit" || cmd.trim() == "exit" {
                 InputCmd::Quit
             } else {
                 println!(""); // go to new line to prepare for output
@@ -38,8 +43,12 @@ impl InputHandler for DefaultInputHandler {
         }
     }
 
-    fn print_prompt(&self) {
-        print!("{}", CMD_PROMPT);
-        io::stdout().flush().ok().expect("Could not write prompt to terminal");
+    fn print_prompt(&self) -> io::Result<()> {
+        print!("{}", self.prompt);
+        io::stdout().flush()
+    }
+
+    fn set_prompt(&mut self, prompt: String) {
+        self.prompt = prompt;
     }
 }
\ No newline at end of file
@@ -6,7 +6,9 @@ pub use self::default::DefaultInputHandler;
 mod posix;
 mod default;
 
-const CMD_PROMPT: &'static str = ">> ";
+pub const CMD_PROMPT: &'static str = ">> ";
+/// The prompt shown while an expression is continued from a line ending in an operator
+pub const CONTINUE_PROMPT: &'static str = "... ";
 
 #[derive(Debug)]
 enum Key {
@@ -26,6 +28,12 @@ enum Key {
 
     Backspace,
     Delete,
+    /// Ctrl-D, which ends the input
+    EndOfInput,
+    /// Ctrl-L, which clears the screen
+    CtrlL,
+    /// There is no more input at all, e.g. because the terminal was closed
+    Closed,
 
     Char(char),
     F(u32),
@@ -43,5 +51,7 @@ pub trait InputHandler {
     fn start(&mut self) -> io::Result<()>;
     fn stop(&mut self) -> io::Result<()>;
     fn handle_input(&mut self) -> InputCmd;
-    fn print_prompt(&self);
+    fn print_prompt(&self) -> io::Result<()>;
+    /// Replaces the prompt shown in front of the input
+    fn set_prompt(&mut self, prompt: String);
 }
\ No newline at end of file
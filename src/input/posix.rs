@@ -1,12 +1,16 @@
 use std::io;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write, Stdin};
+use std::cmp::min;
+use std::mem;
 use std::str;
-use unicode_width::{UnicodeWidthStr, UnicodeWidthChar};
+use unicode_width::UnicodeWidthStr;
 use termios::Termios;
 use termios::tcsetattr;
 use termios::{ECHO, ICANON, VTIME, VMIN, TCSANOW};
-use libc::consts::os::posix88::STDIN_FILENO;
-use super::CMD_PROMPT;
+use libc;
+use libc::{STDIN_FILENO, STDOUT_FILENO};
+use calcr::errors::{char_width, columns, printable};
+use super::{CMD_PROMPT, CONTINUE_PROMPT};
 use super::{InputHandler, InputCmd};
 use super::Key;
 
@@ -15,7 +19,6 @@ const UFT8_LEAD: u8     = 0b_1100_0000;
 const UTF8_CONTINUE: u8 = 0b_1000_0000;
 
 const ESC_CHAR: u8 = 0x1B;
-const UNKNOWN_ES: [u8; 2] = [ESC_CHAR, '[' as u8];
 // Escape sequences for "normal" keys
 const UP_ES:      [u8; 3] = [ESC_CHAR, '[' as u8, 'A' as u8];
 const DOWN_ES:    [u8; 3] = [ESC_CHAR, '[' as u8, 'B' as u8];
@@ -42,27 +45,44 @@ const F11_ES:     [u8; 5] = [ESC_CHAR, '[' as u8, '2' as u8, '3' as u8, '~' as u
 const F12_ES:     [u8; 5] = [ESC_CHAR, '[' as u8, '2' as u8, '4' as u8, '~' as u8];
 
 #[derive(Debug)]
-pub struct PosixInputHandler {
+pub struct PosixInputHandler<R = Stdin> {
+    reader: R,              // Where the input is read from, which is normally stdin
     byte_buf: [u8; 32],     // Byte buffer, which is filled when reading
-    byte_count: usize,      // Number of bytes used in the byte buffer
+    byte_start: usize,      // Index of the first unconsumed byte in the byte buffer
+    byte_count: usize,      // Number of unconsumed bytes in the byte buffer
     line_hist: Vec<String>, // The line history
     line_buf: Vec<String>,  // An editable buffer of the previous- and the current line
     line_idx: usize,        // The index in the line buffer
     line_byte_pos: usize,   // The byte position in the current line
     cursor_pos: usize,      // The cursor position in the current line
+    history_size: usize,    // The maximum number of lines kept in the line history
+    prompt: String,         // The prompt shown in front of the current line
+    continued: String,      // The previous lines of the expression, if they ended in an operator
     orig_termios: Option<Termios>,
 }
 
 impl PosixInputHandler {
-    pub fn new() -> PosixInputHandler {
+    pub fn new(history_size: usize) -> PosixInputHandler {
+        PosixInputHandler::with_reader(io::stdin(), history_size)
+    }
+}
+
+impl<R: Read> PosixInputHandler<R> {
+    /// Creates an input handler reading from `reader` instead of stdin
+    fn with_reader(reader: R, history_size: usize) -> PosixInputHandler<R> {
         let mut out = PosixInputHandler {
+            reader: reader,
             byte_buf: [0; 32],
+            byte_start: 0,
             byte_count: 0,
             line_hist: Vec::new(),
             line_buf: Vec::new(),
             line_idx: 0,
             line_byte_pos: 0,
             cursor_pos: 0,
+            history_size: history_size,
+            prompt: CMD_PROMPT.to_string(),
+            continued: String::new(),
             orig_termios: None,
         };
         out.line_buf.push(String::new());
@@ -71,15 +91,17 @@ impl PosixInputHandler {
 
     /// Blocks while waiting for the user to press a key
     fn poll_keypress(&mut self) -> Key {
-        if self.byte_count == 0 {
-            self.poll_stdin();
+        if self.byte_count == 0 && !self.poll_stdin() {
+            return Key::Closed;
         }
-        let byte = self.byte_buf[0];
+        let byte = self.bytes()[0];
         let (key, byte_len) = match byte {
             ESC_CHAR => self.parse_esc_seq(),
             0x7F => (Key::Backspace, 1), // Yes backspace is mapped to DEL
             0x09 => (Key::Tab, 1),
             0x0A => (Key::Enter, 1),
+            0x04 => (Key::EndOfInput, 1),
+            0x0C => (Key::CtrlL, 1),
             0x20...0x7E => (Key::Char(byte as char), 1), // printable ASCII
             byte if is_utf8_lead(byte) => self.parse_utf8_char(), // utf8 codepoint
             // We don't know, so consume this byte and let the caller deal with it
@@ -89,17 +111,34 @@ impl PosixInputHandler {
         key
     }
 
-    /// Blocks while populating `self.byte_buf` with a chunk of bytes from stdin
-    fn poll_stdin(&mut self) {
-        let read = io::stdin().read(&mut self.byte_buf[self.byte_count..])
-            .ok()
-            .expect("Could not read from terminal");
+    /// Blocks while populating `self.byte_buf` with a chunk of bytes from the reader
+    ///
+    /// Returns false if no bytes could be read, since the end of the input was reached.
+    fn poll_stdin(&mut self) -> bool {
+        // move the unconsumed bytes to the front, to make as much room as possible after them
+        if self.byte_start > 0 {
+            self.byte_buf.copy_within(self.byte_start..self.byte_start + self.byte_count, 0);
+            self.byte_start = 0;
+        }
+        let read = loop {
+            match self.reader.read(&mut self.byte_buf[self.byte_count..]) {
+                // a signal, e.g. from resizing the terminal, arrived before anything was read
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                result => break result.expect("Could not read from terminal"),
+            }
+        };
         self.byte_count += read;
+        read > 0
+    }
+
+    /// Returns the bytes which have been read but not yet consumed
+    fn bytes(&self) -> &[u8] {
+        &self.byte_buf[self.byte_start..self.byte_start + self.byte_count]
     }
 
     fn parse_esc_seq(&self) -> (Key, usize) {
         // as of now these are the only sequences we deal with
-        match self.byte_buf {
+        match self.bytes() {
             // normal keys
             buf if buf.starts_with(&UP_ES) => (Key::Up, UP_ES.len()),
             buf if buf.starts_with(&DOWN_ES) => (Key::Down, DOWN_ES.len()),
@@ -124,17 +163,37 @@ impl PosixInputHandler {
             buf if buf.starts_with(&F10_ES) => (Key::F(10), F10_ES.len()),
             buf if buf.starts_with(&F11_ES) => (Key::F(11), F11_ES.len()),
             buf if buf.starts_with(&F12_ES) => (Key::F(12), F12_ES.len()),
-            // unknown escape sequence
-            buf if buf.starts_with(&UNKNOWN_ES) => (Key::Unknown, UNKNOWN_ES.len()),
-            // we didn't match any escape sequence, so we assume it is the escape key
-            _ => (Key::Esc, 1),
+            // only a lone escape byte is the escape key itself
+            _ if self.byte_count <= 1 => (Key::Esc, 1),
+            // an unknown escape sequence, e.g. a mouse event, which must be consumed entirely
+            _ => (Key::Unknown, self.unknown_esc_seq_len()),
+        }
+    }
+
+    /// Returns the length of the unknown escape sequence at the start of the buffer
+    fn unknown_esc_seq_len(&self) -> usize {
+        let bytes = self.bytes();
+        match bytes[1] {
+            b'[' => {
+                // a control sequence ends with a byte in the range 0x40 to 0x7E, after any number
+                // of parameter and intermediate bytes
+                for i in 2..bytes.len() {
+                    let byte = bytes[i];
+                    if byte >= 0x40 && byte <= 0x7E {
+                        return i + 1;
+                    }
+                }
+                bytes.len()
+            },
+            b'O' => min(3, bytes.len()),
+            // most likely a key pressed while holding alt
+            _ => 2,
         }
     }
 
     fn parse_utf8_char(&mut self) -> (Key, usize) {
-        let mut bytes = Vec::new();
         let mut char_len = 2; // since we are bothering to parse ut8, the char is at least 2 bytes
-        let lead_byte = self.byte_buf[0];
+        let lead_byte = self.bytes()[0];
 
         // get the length of the utf8 char from the lead byte
         for i in char_len..8 {
@@ -144,19 +203,14 @@ impl PosixInputHandler {
                 char_len += 1;
             }
         }
-        let mut idx = 0;
-        for _ in 0..char_len {
-            if idx >= self.byte_count {
-                // no more bytes, but we are not done, so poll some more
-                self.byte_count = 0;
-                self.poll_stdin();
-                idx = 0;
+        while self.byte_count < char_len {
+            // the rest of the char has not been read yet, so poll some more
+            if !self.poll_stdin() {
+                return (Key::Closed, 0);
             }
-            bytes.push(self.byte_buf[idx]);
-            idx += 1;
         }
         // now we try to parse it to a char
-        match str::from_utf8(&bytes[..]) {
+        match str::from_utf8(&self.bytes()[..char_len]) {
             Ok(s) => (Key::Char(s.chars().next().unwrap()), char_len),
             Err(_) => (Key::Unknown, char_len),
         }
@@ -164,17 +218,14 @@ impl PosixInputHandler {
 
     /// Consumes `count` bytes from the front of the the buffer
     ///
-    /// The first `count` bytes are removed from the buffer by moving the rest of the bytes
-    /// forwards.
+    /// Only the start of the buffer is moved, so this takes constant time. The remaining bytes
+    /// are instead moved to the front of the buffer once more bytes are read.
     fn consume_buffer(&mut self, count: usize) {
-        for i in 0..self.byte_count {
-            self.byte_buf[i] = if i + count < self.byte_buf.len() {
-                self.byte_buf[i+count]
-            } else {
-                0
-            };
-        }
+        self.byte_start += count;
         self.byte_count -= count;
+        if self.byte_count == 0 {
+            self.byte_start = 0;
+        }
     }
 
     /// Moves `line_byte_pos` forward so it points to the next utf8 codepoint
@@ -195,9 +246,11 @@ impl PosixInputHandler {
 
     /// Moves `line_byte_pos` backwards so it points to the previous utf8 codepoint
     ///
-    /// # Panics
-    /// This function panics if the current line ends before the previous utf8 codepoint
-    fn to_prev_char(&mut self) -> char {
+    /// Returns `None` without moving if we are already at the start of the line.
+    fn to_prev_char(&mut self) -> Option<char> {
+        if self.line_byte_pos == 0 {
+            return None;
+        }
         let end = self.line_byte_pos;
         self.line_byte_pos -= 1;
         while is_utf8_continue(self.line_byte_at(self.line_byte_pos)) {
@@ -207,10 +260,31 @@ impl PosixInputHandler {
         unsafe {
             // Since the line buffer only contains valid utf8, there is no need to verify it again
             // before turning it into a strin
-            str::from_utf8_unchecked(bytes).chars().next().unwrap()
+            str::from_utf8_unchecked(bytes).chars().next()
         }
     }
 
+    /// Adds `line` to the line history, and starts a new empty line
+    ///
+    /// If the history is full, the oldest lines are dropped to make room.
+    fn push_history(&mut self, line: String) {
+        self.line_hist.push(line);
+        if self.line_hist.len() > self.history_size {
+            let excess = self.line_hist.len() - self.history_size;
+            self.line_hist.drain(..excess);
+        }
+        self.reset_lines();
+    }
+
+    /// Discards any edits to the history, and moves to a new empty line
+    fn reset_lines(&mut self) {
+        self.line_buf = self.line_hist.clone();
+        self.line_buf.push(String::new());
+        self.line_idx = self.line_buf.len() - 1;
+        self.line_byte_pos = 0;
+        self.cursor_pos = 0;
+    }
+
     /// Returns the `u8` at `idx`
     ///
     /// # Panics
@@ -235,11 +309,55 @@ impl PosixInputHandler {
     /// This function panics if `line_buf` is empty,
     /// or the line contains more than `usize::MAX` chars.
     fn line_column_len(&self) -> usize {
-        self.line_buf[self.line_idx].width()
+        columns(self.line_buf[self.line_idx].chars())
     }
 
 }
 
+/// Returns the width of the terminal in columns, or `None` if it could not be found
+fn terminal_width() -> Option<usize> {
+    unsafe {
+        let mut size: libc::winsize = mem::zeroed();
+        if libc::ioctl(STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0 {
+            Some(size.ws_col as usize)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the part of `line` starting at column `start_col` which fits within `max_width`
+///
+/// Since wide chars can't be split, the part may start a bit later than `start_col`, so the column
+/// it actually starts at is returned as well.
+fn visible_part(line: &str, start_col: usize, max_width: usize) -> (&str, usize) {
+    let mut col = 0;
+    let mut start = None;
+    for (idx, ch) in line.char_indices() {
+        let width = char_width(ch);
+        match start {
+            None if col >= start_col => start = Some((idx, col)),
+            Some((start_idx, start_col)) if col + width - start_col > max_width => {
+                return (&line[start_idx..idx], start_col);
+            },
+            _ => {},
+        }
+        col += width;
+    }
+    match start {
+        Some((start_idx, start_col)) => (&line[start_idx..], start_col),
+        None => ("", col),
+    }
+}
+
+/// Whether `line` ends in a binary operator, meaning the expression continues on the next line
+fn ends_in_operator(line: &str) -> bool {
+    match line.trim_end().chars().last() {
+        Some('+') | Some('-') | Some('*') | Some('/') | Some('^') | Some('=') => true,
+        _ => false,
+    }
+}
+
 fn is_utf8_lead(byte: u8) -> bool {
     byte & UFT8_MASK == UFT8_LEAD
 }
@@ -248,7 +366,7 @@ fn is_utf8_continue(byte: u8) -> bool {
     byte & UFT8_MASK == UTF8_CONTINUE
 }
 
-impl InputHandler for PosixInputHandler {
+impl<R: Read> InputHandler for PosixInputHandler<R> {
     fn start(&mut self) -> io::Result<()> {
         // Only start if we are not already running
         if self.orig_termios.is_none() {
@@ -278,27 +396,44 @@ impl InputHandler for PosixInputHandler {
 
     fn handle_input(&mut self) -> InputCmd {
         match self.poll_keypress() {
-            Key::Esc => InputCmd::Quit,
+            Key::Esc => {
+                self.continued.clear();
+                self.line_buf[self.line_idx].clear();
+                self.line_byte_pos = 0;
+                self.cursor_pos = 0;
+                InputCmd::None
+            },
+            // like in most shells, Ctrl-D only quits when there is nothing to lose
+            Key::EndOfInput if self.line_buf[self.line_idx].is_empty() => InputCmd::Quit,
+            Key::Closed => InputCmd::Quit,
+            Key::CtrlL => {
+                // clear the screen and move the cursor to the top, after which the prompt and
+                // the current line are redrawn like after any other key
+                print!("\x1B[2J\x1B[H");
+                InputCmd::None
+            },
             Key::Enter => {
-                let cmd = self.line_buf[self.line_idx].clone();
+                let line = self.line_buf[self.line_idx].clone();
+                let cmd = format!("{}{}", self.continued, line);
                 if cmd == "quit" || cmd == "exit" {
                     InputCmd::Quit
+                } else if ends_in_operator(&line) {
+                    // e.g. a pasted formula broken across lines, so wait for the rest of it
+                    self.continued = cmd + " ";
+                    self.reset_lines();
+                    println!("");
+                    InputCmd::None
                 } else {
-                    self.line_hist.push(cmd.clone());
-                    self.line_buf = self.line_hist.clone();
-                    self.line_buf.push(String::new());
-                    self.line_idx = self.line_buf.len() - 1;
-                    self.line_byte_pos = 0;
-                    self.cursor_pos = 0;
+                    self.continued.clear();
+                    self.push_history(cmd.clone());
                     println!(""); // go to new line to prepare for output
                     InputCmd::Equation(cmd)
                 }
             },
             Key::Backspace => {
-                if self.line_byte_pos > 0 {
-                    self.to_prev_char();
+                if let Some(ch) = self.to_prev_char() {
                     self.line_buf[self.line_idx].remove(self.line_byte_pos);
-                    self.cursor_pos -= 1;
+                    self.cursor_pos -= char_width(ch);
                 }
                 InputCmd::None
             },
@@ -327,14 +462,13 @@ impl InputHandler for PosixInputHandler {
             Key::Right => {
                 if self.cursor_pos < self.line_column_len() {
                     let ch = self.to_next_char();
-                    self.cursor_pos += ch.width().unwrap_or(0);
+                    self.cursor_pos += char_width(ch);
                 }
                 InputCmd::None
             },
             Key::Left => {
-                if self.cursor_pos > 0 {
-                    let ch = self.to_prev_char();
-                    self.cursor_pos -= ch.width().unwrap_or(0);
+                if let Some(ch) = self.to_prev_char() {
+                    self.cursor_pos -= char_width(ch);
                 }
                 InputCmd::None
             },
@@ -351,26 +485,46 @@ impl InputHandler for PosixInputHandler {
             Key::Char(ch) => {
                 self.line_buf[self.line_idx].insert(self.line_byte_pos, ch);
                 self.line_byte_pos += ch.len_utf8();
-                self.cursor_pos += ch.width().unwrap_or(0);
+                self.cursor_pos += char_width(ch);
                 InputCmd::None
             },
-            // For now we explicitly ignore these keys
-            Key::Insert | Key::PgUp | Key::PgDown => InputCmd::None,
+            // For now we explicitly ignore these keys. Tab is kept out of the line, since an
+            // invisible tab would make the cursor position wrong.
+            Key::Insert | Key::PgUp | Key::PgDown | Key::Tab => InputCmd::None,
             _ => InputCmd::None,
         }
     }
 
-    fn print_prompt(&self) {
+    fn print_prompt(&self) -> io::Result<()> {
+        let prompt = if self.continued.is_empty() {
+            self.prompt.clone()
+        } else {
+            // right aligned, so the lines of the expression start in the same column
+            format!("{:>1$}", CONTINUE_PROMPT, self.prompt.width())
+        };
+        let prompt_width = prompt.width();
+        // Leave the last column free, so the terminal never wraps the line. If the line is too
+        // long to fit, we scroll it horizontally so the cursor stays visible.
+        let max_width = terminal_width().map_or(usize::max_value(), |width| {
+            width.saturating_sub(prompt_width + 1)
+        });
+        let start_col = self.cursor_pos.saturating_sub(max_width);
+        let (visible, start_col) = visible_part(&self.line_buf[self.line_idx], start_col,
+                                                max_width);
         print!("\r\x1B[K"); // move back to the beginning of the line, and erase the old line
-        print!("{}{}", CMD_PROMPT, self.line_buf[self.line_idx]); // print the current line
-        print!("\r\x1B[{}C", self.cursor_pos + CMD_PROMPT.len()); // print the cursor
+        print!("{}{}", prompt, printable(visible)); // print the current line
+        print!("\r\x1B[{}C", self.cursor_pos - start_col + prompt_width); // print the cursor
         // We explicitly call flush on stdout, or else the line won't be printed untill
         // after the user presses a key.
-        io::stdout().flush().ok().expect("Could not write prompt to terminal");
+        io::stdout().flush()
+    }
+
+    fn set_prompt(&mut self, prompt: String) {
+        self.prompt = prompt;
     }
 }
 
-impl Drop for PosixInputHandler {
+impl<R> Drop for PosixInputHandler<R> {
     fn drop(&mut self) {
         if let Some(orig_termios) = self.orig_termios {
             // This must succeed, or the terminal is screwed, which means there is no point in
@@ -380,4 +534,260 @@ impl Drop for PosixInputHandler {
                 .expect("Could not restore terminal settings");
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::Read;
+    use super::{PosixInputHandler, visible_part};
+    use super::super::{Key, InputHandler, InputCmd};
+
+    /// Makes `ih` read `bytes` as if they came from stdin
+    fn fill_buffer<R>(ih: &mut PosixInputHandler<R>, bytes: &[u8]) {
+        ih.byte_buf[..bytes.len()].copy_from_slice(bytes);
+        ih.byte_start = 0;
+        ih.byte_count = bytes.len();
+    }
+
+    #[test]
+    fn unknown_esc_seq() {
+        let mut ih = PosixInputHandler::new(10);
+        // a mouse click followed by an up arrow
+        fill_buffer(&mut ih, b"\x1B[<0;10;5M\x1B[A");
+        match ih.poll_keypress() {
+            Key::Unknown => {},
+            key => panic!("expected Key::Unknown, got {:?}", key),
+        }
+        match ih.poll_keypress() {
+            Key::Up => {},
+            key => panic!("expected Key::Up, got {:?}", key),
+        }
+        assert_eq!(ih.byte_count, 0);
+        fill_buffer(&mut ih, b"\x1B");
+        match ih.poll_keypress() {
+            Key::Esc => {},
+            key => panic!("expected Key::Esc, got {:?}", key),
+        }
+    }
+
+    #[test]
+    fn esc_clears_line() {
+        let mut ih = PosixInputHandler::new(10);
+        fill_buffer(&mut ih, b"1+2\x1B");
+        for _ in 0..4 {
+            if let InputCmd::Quit = ih.handle_input() {
+                panic!("escape should not quit");
+            }
+        }
+        assert_eq!(ih.line_buf[ih.line_idx], "");
+        assert_eq!(ih.line_byte_pos, 0);
+        assert_eq!(ih.cursor_pos, 0);
+        // Ctrl-D quits, but only when the line is empty
+        fill_buffer(&mut ih, b"1\x04");
+        for _ in 0..2 {
+            if let InputCmd::Quit = ih.handle_input() {
+                panic!("Ctrl-D should not quit with text on the line");
+            }
+        }
+        fill_buffer(&mut ih, b"\x1B");
+        ih.handle_input();
+        fill_buffer(&mut ih, b"\x04");
+        match ih.handle_input() {
+            InputCmd::Quit => {},
+            _ => panic!("Ctrl-D should quit on an empty line"),
+        }
+    }
+
+    #[test]
+    fn clear_screen_keeps_line() {
+        let mut ih = PosixInputHandler::new(10);
+        fill_buffer(&mut ih, b"1+2\x0C");
+        for _ in 0..4 {
+            match ih.handle_input() {
+                InputCmd::None => {},
+                _ => panic!("expected no command"),
+            }
+        }
+        assert_eq!(ih.line_buf[ih.line_idx], "1+2");
+        assert_eq!(ih.cursor_pos, 3);
+    }
+
+    #[test]
+    fn tab_is_ignored() {
+        let mut ih = PosixInputHandler::new(10);
+        fill_buffer(&mut ih, b"\t1\t+2");
+        for _ in 0..5 {
+            match ih.handle_input() {
+                InputCmd::None => {},
+                _ => panic!("expected no command"),
+            }
+        }
+        assert_eq!(ih.line_buf[ih.line_idx], "1+2");
+        assert_eq!(ih.cursor_pos, 3);
+    }
+
+    #[test]
+    fn interleaved_reads() {
+        // each read only gets the bytes of one of the slices, splitting the € in two
+        let reader = (&b"a\x1B[Ab\xE2"[..]).chain(&b"\x82\xACcd"[..]);
+        let mut ih = PosixInputHandler::with_reader(reader, 10);
+        let mut keys = Vec::new();
+        loop {
+            match ih.poll_keypress() {
+                Key::Closed => break,
+                Key::Char(ch) => keys.push(ch),
+                Key::Up => keys.push('↑'),
+                key => panic!("unexpected key {:?}", key),
+            }
+        }
+        assert_eq!(keys, vec!('a', '↑', 'b', '€', 'c', 'd'));
+        assert_eq!(ih.byte_count, 0);
+    }
+
+    #[test]
+    fn closed_input() {
+        let mut ih = PosixInputHandler::with_reader(io::empty(), 10);
+        match ih.handle_input() {
+            InputCmd::Quit => {},
+            _ => panic!("expected the handler to quit when the input is closed"),
+        }
+        // a partial utf8 char at the end of the input
+        let mut ih = PosixInputHandler::with_reader(io::empty(), 10);
+        fill_buffer(&mut ih, &"π".as_bytes()[..1]);
+        match ih.handle_input() {
+            InputCmd::Quit => {},
+            _ => panic!("expected the handler to quit when the input is closed"),
+        }
+    }
+
+    /// A reader which is interrupted by a signal before each byte it gives
+    struct InterruptedReader {
+        bytes: Vec<u8>,
+        interrupted: bool,
+    }
+
+    impl Read for InterruptedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupted = !self.interrupted;
+            if self.interrupted {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+            }
+            if self.bytes.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.bytes.remove(0);
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn interrupted_read() {
+        let reader = InterruptedReader { bytes: b"1+2\n".to_vec(), interrupted: false };
+        let mut ih = PosixInputHandler::with_reader(reader, 10);
+        loop {
+            match ih.handle_input() {
+                InputCmd::None => {},
+                InputCmd::Equation(eq) => {
+                    assert_eq!(eq, "1+2");
+                    break;
+                },
+                _ => panic!("expected the equation to be read despite the interruptions"),
+            }
+        }
+    }
+
+    #[test]
+    fn prev_char_at_line_start() {
+        let mut ih = PosixInputHandler::new(10);
+        assert_eq!(ih.to_prev_char(), None);
+        ih.line_buf[0] = "aπ".to_string();
+        ih.line_byte_pos = ih.line_byte_len();
+        assert_eq!(ih.to_prev_char(), Some('π'));
+        assert_eq!(ih.to_prev_char(), Some('a'));
+        assert_eq!(ih.to_prev_char(), None);
+        assert_eq!(ih.line_byte_pos, 0);
+    }
+
+    #[test]
+    fn scrolled_line() {
+        assert_eq!(visible_part("1 + 2", 0, 80), ("1 + 2", 0));
+        assert_eq!(visible_part("1 + 2", 0, 3), ("1 +", 0));
+        assert_eq!(visible_part("1 + 2", 2, 3), ("+ 2", 2));
+        assert_eq!(visible_part("1 + 2", 5, 3), ("", 5));
+        // wide chars are never split
+        assert_eq!(visible_part("a日本", 2, 2), ("本", 3));
+        assert_eq!(visible_part("日本", 0, 3), ("日", 0));
+    }
+
+    #[test]
+    fn control_char_width() {
+        let mut ih = PosixInputHandler::new(10);
+        // a pasted C1 control char, which is shown as one placeholder column
+        fill_buffer(&mut ih, "1\u{85}+".as_bytes());
+        for _ in 0..3 {
+            ih.handle_input();
+        }
+        assert_eq!(ih.cursor_pos, 3);
+        fill_buffer(&mut ih, b"\x1B[D\x7F");
+        for _ in 0..2 {
+            ih.handle_input();
+        }
+        assert_eq!(ih.line_buf[ih.line_idx], "1+");
+        assert_eq!(ih.cursor_pos, 1);
+        assert_eq!(visible_part("1\u{85}+2", 2, 2), ("+2", 2));
+    }
+
+    #[test]
+    fn enter_on_edited_history() {
+        let mut ih = PosixInputHandler::new(10);
+        ih.push_history("1+2".to_string());
+        ih.push_history("3*4".to_string());
+        // edit both lines, go back down to the first edit, and then up to the second one
+        fill_buffer(&mut ih, b"\x1B[A5\x1B[A0\x1B[B");
+        for _ in 0..5 {
+            ih.handle_input();
+        }
+        assert_eq!(ih.line_buf, vec!("1+20", "3*45", ""));
+        fill_buffer(&mut ih, b"\x1B[A\n");
+        ih.handle_input();
+        match ih.handle_input() {
+            InputCmd::Equation(ref cmd) if cmd == "1+20" => {},
+            _ => panic!("expected the edited line to be evaluated"),
+        }
+        // the edited line is added as the newest entry, and the other edits are discarded
+        assert_eq!(ih.line_hist, vec!("1+2", "3*4", "1+20"));
+        assert_eq!(ih.line_buf, vec!("1+2", "3*4", "1+20", ""));
+        assert_eq!(ih.line_idx, 3);
+    }
+
+    #[test]
+    fn continued_lines() {
+        let mut ih = PosixInputHandler::new(10);
+        fill_buffer(&mut ih, b"1 +\n2 *  \n3\n");
+        for _ in 0..11 {
+            match ih.handle_input() {
+                InputCmd::None => {},
+                _ => panic!("expected the expression to continue"),
+            }
+        }
+        match ih.handle_input() {
+            InputCmd::Equation(ref cmd) if cmd == "1 + 2 *   3" => {},
+            _ => panic!("expected the joined lines to be evaluated"),
+        }
+        // only the whole expression is added to the history
+        assert_eq!(ih.line_hist, vec!("1 + 2 *   3"));
+        assert!(ih.continued.is_empty());
+    }
+
+    #[test]
+    fn history_size() {
+        let mut ih = PosixInputHandler::new(3);
+        for i in 0..10 {
+            ih.push_history(i.to_string());
+        }
+        assert_eq!(ih.line_hist, vec!("7", "8", "9"));
+        assert_eq!(ih.line_buf, vec!("7", "8", "9", ""));
+        assert_eq!(ih.line_idx, 3);
+    }
+}
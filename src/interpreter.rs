@@ -1,17 +1,174 @@
 use std::f64;
-use std::collections::HashMap;
-use ast::{Ast, ConstKind, FuncKind, OpKind};
+use std::mem;
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet, BTreeMap};
+use ast::{Ast, AstVal, ConstKind, FuncKind, OpKind};
 use ast::AstVal::*;
 use ast::FuncKind::*;
 use ast::OpKind::*;
 use ast::ConstKind::*;
-use lexer::lex_equation;
-use parser::parse_tokens;
+use lexer::{lex_equation_with_options, LexerOptions};
+use parser::{parse_tokens_with_functions, get_builtin_name, find_builtin, is_comparison,
+             is_reserved_name, BUILTINS};
+use rpn::parse_rpn;
 use errors::{CalcrResult, CalcrError};
+use token::{Token, TokVal, Span};
+use token::TokVal::Semicolon;
+use token::OpKind as TokOp;
+use output::{OutputFormat, DisplayMode, format_in_base};
+use number::Number;
+
+/// The number of changes to the interpreter state which are remembered for `undo`
+const MAX_UNDO: usize = 100;
+
+/// The limit on how deep into an `Ast` the interpreter will recurse while evaluating it
+///
+/// Each level takes a few kilobytes of stack in a debug build, so this keeps evaluation within the
+/// 2 MiB stack Rust gives to new threads, where 1024 levels did not fit.
+const MAX_EVAL_DEPTH: u32 = 512;
+
+/// The coefficients of the Lanczos approximation of the gamma function, with g = 7
+const LANCZOS_G: f64 = 7.0;
+const LANCZOS_COEFFS: [f64; 9] = [
+    0.99999999999980993,
+    676.5203681218851,
+    -1259.1392167224028,
+    771.32342877765313,
+    -176.61502916214059,
+    12.507343278686905,
+    -0.13857109526572012,
+    9.9843695780195716e-6,
+    1.5056327351493116e-7,
+];
+
+/// The variable which always holds the last result, like `ans`
+const LAST_RESULT_VAR: &'static str = "_";
+
+/// The unit used for angles by the trigonometric functions
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AngleMode {
+    Radians,
+    Degrees,
+}
+
+impl AngleMode {
+    /// Returns the short name of the mode, e.g. for showing it in the prompt
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AngleMode::Radians => "rad",
+            AngleMode::Degrees => "deg",
+        }
+    }
+}
+
+/// The implementation of a registered function, which is given the values of the arguments
+type ExternFnImpl = Box<dyn Fn(&[f64]) -> CalcrResult<f64>>;
+
+/// Places the given text on the clipboard for the `copy` command
+type ClipboardFn = Box<dyn Fn(&str) -> CalcrResult<()>>;
+
+/// A function registered by the user of the `Interpreter`, along with how many arguments it takes
+struct ExternFn {
+    arity: usize,
+    func: ExternFnImpl,
+}
+
+/// How close to zero the denominator of `sec`, `csc` or `cot` must be for it to count as zero
+const RECIPROCAL_ZERO: f64 = 1e-15;
+
+/// The largest number of coefficients `cf` computes of a continued fraction
+const MAX_CONT_FRAC_COEFFS: f64 = 100.0;
+
+/// The default limit on the argument of the factorial function
+pub const DEFAULT_MAX_FACTORIAL: u64 = 10000;
+
+/// Physical constants in SI units, which are only available after `set_physics(true)` since
+/// their short names would otherwise clash with variables
+const PHYSICS_CONSTANTS: [(&'static str, f64); 5] = [
+    ("c", 299792458.0),       // the speed of light in m/s
+    ("h", 6.62607015e-34),    // the Planck constant in J s
+    ("N_A", 6.02214076e23),   // the Avogadro constant in 1/mol
+    ("k_B", 1.380649e-23),    // the Boltzmann constant in J/K
+    ("G", 6.67430e-11),       // the gravitational constant in m^3/(kg s^2)
+];
+
+/// The result of an expression, tagged with how it should be shown by `format_answer`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Answer {
+    /// A number, along with the exact fraction it is equal to (if any)
+    Number(f64, Option<(i64, i64)>),
+    /// The truth value given by a comparison or logical operator, which is either 1 or 0
+    Bool(f64),
+    /// An imaginary number, given by its magnitude
+    Imaginary(f64),
+    /// A whole number to be shown in the given base, as given by `tobase`
+    InBase(f64, u32),
+    /// The value of the continued fraction with the given coefficients, as given by `cf`
+    ContFrac(f64, Vec<i64>),
+    /// The solution found by `solve` for the named unknown, along with its exact fraction (if any)
+    Solution(String, f64, Option<(i64, i64)>),
+}
+
+impl Answer {
+    /// The value of the answer as a plain number, which is the magnitude if it is imaginary
+    pub fn value(&self) -> f64 {
+        match *self {
+            Answer::Number(val, _) | Answer::Bool(val) | Answer::Imaginary(val) |
+            Answer::InBase(val, _) | Answer::ContFrac(val, _) | Answer::Solution(_, val, _) => val,
+        }
+    }
+}
+
+/// A change to the state of an `Interpreter`, storing what is needed to undo it
+enum StateChange {
+    /// A variable was assigned, and this was its previous value (if any)
+    Var(String, Option<f64>),
+    /// Several variables were assigned at once, and these were their previous values
+    Vars(Vec<(String, Option<f64>)>),
+    /// The last result was updated, and this was its previous value and whether it was imaginary
+    LastResult(f64, bool),
+    /// Everything was reset, and these were the previous variables and last result
+    Reset(HashMap<String, f64>, f64, bool),
+    /// A result was named, and this was the previous result of that name (if any)
+    NamedResult(String, Option<f64>),
+    /// Several changes were made by a single expression, which are undone together
+    Several(Vec<StateChange>),
+}
 
 pub struct Interpreter {
     vars: HashMap<String, f64>,
     last_result: f64,
+    /// Whether the last result is imaginary, in which case `last_result` is its magnitude
+    ans_imaginary: bool,
+    /// The text shown by the most recent command, e.g. the steps given by `explain`
+    command_output: Option<String>,
+    depth: u32,
+    format: OutputFormat,
+    lexer_options: LexerOptions,
+    undo_stack: Vec<StateChange>,
+    batch_results: Vec<f64>,
+    results: BTreeMap<String, f64>,
+    angle_mode: AngleMode,
+    functions: HashMap<String, ExternFn>,
+    max_factorial: u64,
+    rpn: bool,
+    physics: bool,
+    /// Whether whole numbers and fractions are computed exactly, rather than as `f64`s
+    exact: bool,
+    /// Whether results which are NaN or infinite are errors
+    strict_domain: bool,
+    /// Whether the square root of a negative number is allowed as the final result
+    imaginary_results: bool,
+    /// When the current evaluation must be done by, if it was given a timeout
+    deadline: Option<Instant>,
+    /// Places text on the clipboard for the `copy` command
+    clipboard: ClipboardFn,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
 }
 
 impl Interpreter {
@@ -19,75 +176,1264 @@ impl Interpreter {
         Interpreter {
             vars: HashMap::new(),
             last_result: 0.0,
+            ans_imaginary: false,
+            command_output: None,
+            depth: 0,
+            format: OutputFormat::new(),
+            lexer_options: LexerOptions::new(),
+            undo_stack: Vec::new(),
+            batch_results: Vec::new(),
+            results: BTreeMap::new(),
+            angle_mode: AngleMode::Radians,
+            functions: HashMap::new(),
+            max_factorial: DEFAULT_MAX_FACTORIAL,
+            rpn: false,
+            physics: false,
+            exact: true,
+            strict_domain: false,
+            imaginary_results: false,
+            deadline: None,
+            clipboard: Box::new(no_clipboard),
         }
     }
 
     pub fn eval_expression(&mut self, expr: &String) -> CalcrResult<Option<f64>> {
-        let toks = try!(lex_equation(expr));
-        let ast = try!(parse_tokens(toks));
-        let result = self.eval_expr(&ast);
+        self.eval_answer(expr).map(|answer| answer.map(|answer| answer.value()))
+    }
+
+    /// Evaluates `expr` like `eval_expression`, but returns the result tagged with how it should
+    /// be shown, e.g. as `true` or `false` for a comparison
+    pub fn eval_answer(&mut self, expr: &String) -> CalcrResult<Option<Answer>> {
+        self.command_output = None;
+        if let Some(result) = self.eval_command(expr) {
+            return result;
+        }
+        // a leading `?` evaluates the expression without storing the result in `ans`
+        let update_ans = !expr.trim_start().starts_with('?');
+        let batch = try!(self.lex_batch(expr));
+        self.batch_results.clear();
+        let mut result = Ok(None);
+        for toks in batch {
+            let span = match (toks.first(), toks.last()) {
+                (Some(first), Some(last)) => Some((first.span.0, last.span.1)),
+                _ => None,
+            };
+            let ast = try!(self.parse_toks(toks));
+            result = self.eval_result(&ast, update_ans, span);
+            match result {
+                // imaginary results cannot be used in further calculations, so `prev` gives NaN
+                Ok(Some(Answer::Imaginary(_))) => self.batch_results.push(f64::NAN),
+                Ok(Some(ref answer)) => self.batch_results.push(answer.value()),
+                Ok(None) => {},
+                Err(_) => break,
+            }
+        }
+        result
+    }
+
+    /// Evaluates `expr` like `eval_expression`, but fails if this takes longer than `timeout`
+    ///
+    /// The time is checked before each step of the evaluation, and on every pass of the loops in
+    /// the factorial and `solve`. It is not checked while a registered function runs, so a
+    /// single slow call to one is not interrupted, and the evaluation only fails once it returns.
+    /// A timed out expression leaves `ans` and the variables unchanged, but any expressions before
+    /// it in a batch have still been evaluated. A timeout too long to represent means there is no
+    /// limit.
+    pub fn eval_with_timeout(&mut self, expr: &String, timeout: Duration)
+        -> CalcrResult<Option<f64>> {
+        self.deadline = Instant::now().checked_add(timeout);
+        let result = self.eval_expression(expr);
+        self.deadline = None;
+        result
+    }
+
+    /// Evaluates `expr` for `steps` evenly spaced values of the variable `var` from `start` to
+    /// `end`, both included, returning each value along with the result
+    ///
+    /// The expression is only parsed once. `ans` is not updated, and `var` gets back its
+    /// previous value (if any) afterwards.
+    pub fn eval_over(&mut self, expr: &str, var: &str, start: f64, end: f64, steps: usize)
+        -> CalcrResult<Vec<(f64, f64)>> {
+        let toks = try!(lex_equation_with_options(&expr.to_string(), self.lexer_options));
+        let ast = try!(self.parse_toks(toks));
+        let prev = self.vars.get(var).cloned();
+        let mut points = Vec::with_capacity(steps);
+        let mut result = Ok(());
+        for i in 0..steps {
+            let x = if steps == 1 {
+                start
+            } else {
+                start + (end - start) * i as f64 / (steps - 1) as f64
+            };
+            self.vars.insert(var.to_string(), x);
+            match self.eval_eq(&ast) {
+                Ok(y) => points.push((x, y)),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                },
+            }
+        }
+        match prev {
+            Some(val) => self.vars.insert(var.to_string(), val),
+            None => self.vars.remove(var),
+        };
+        result.map(|_| points)
+    }
+
+    /// Checks that `expr` can be parsed, without evaluating it
+    ///
+    /// Only syntax errors are reported, so e.g. `sqrt(-1) + 1` passes the check even though
+    /// evaluating it fails. Commands are not recognized, and are checked like expressions.
+    pub fn check_expression(&self, expr: &String) -> CalcrResult<()> {
+        for toks in try!(self.lex_batch(expr)) {
+            try!(self.parse_toks(toks));
+        }
+        Ok(())
+    }
+
+    /// Lexes `expr` and splits it into the expressions of a batch, ready to be parsed
+    fn lex_batch(&self, expr: &String) -> CalcrResult<Vec<Vec<Token>>> {
+        // a leading `?` is replaced by a space so the spans still line up with the input
+        let expr = if expr.trim_start().starts_with('?') {
+            expr.replacen('?', " ", 1)
+        } else {
+            expr.clone()
+        };
+        let toks = try!(lex_equation_with_options(&expr, self.lexer_options));
+        // expressions separated by semicolons are evaluated one after the other as a batch
+        let mut batch = vec!(Vec::new());
+        for tok in toks {
+            if tok.val == Semicolon {
+                batch.push(Vec::new());
+            } else {
+                batch.last_mut().unwrap().push(tok);
+            }
+        }
+        if batch.len() > 1 {
+            batch.retain(|toks| !toks.is_empty());
+        }
+        for toks in batch.iter_mut() {
+            if !self.rpn && continues_last_result(toks) {
+                // the span is empty, since `ans` does not appear in the input
+                let pos = toks[0].span.0;
+                toks.insert(0, Token { val: TokVal::Name("ans".to_string()), span: (pos, pos) });
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Parses the tokens of a single expression, in reverse Polish notation if enabled
+    fn parse_toks(&self, toks: Vec<Token>) -> CalcrResult<Ast> {
+        if self.rpn { parse_rpn(toks) } else { self.parse_infix(toks) }
+    }
+
+    /// Evaluates each of `exprs` in order, passing each expression and its result to `callback`
+    /// as soon as it has been evaluated
+    ///
+    /// The expressions share state like with `eval_expression`, so e.g. `ans` carries over.
+    pub fn eval_each<I, F>(&mut self, exprs: I, mut callback: F)
+        where I: IntoIterator, I::Item: AsRef<str>, F: FnMut(&str, CalcrResult<Option<f64>>) {
+        for expr in exprs {
+            let expr = expr.as_ref().to_string();
+            let result = self.eval_expression(&expr);
+            callback(&expr, result);
+        }
+    }
+
+    /// Evaluates an already parsed expression, e.g. one built without going through the parser
+    ///
+    /// Like `eval_expression`, this updates `ans` if the expression results in a number.
+    ///
+    /// If imaginary results are allowed and the result is imaginary, `format_answer` shows it
+    /// with an `i` suffix.
+    pub fn eval_ast(&mut self, ast: &Ast) -> CalcrResult<Option<Answer>> {
+        self.eval_result(ast, true, None)
+    }
+
+    /// Evaluates `ast`, storing the result in `ans` only if `update_ans` is true
+    ///
+    /// The `span` of the whole expression is used for the errors of `strict_domain`.
+    fn eval_result(&mut self, ast: &Ast, update_ans: bool, span: Option<Span>)
+        -> CalcrResult<Option<Answer>> {
+        let result = try!(self.eval_expr(ast));
+        let res = match result {
+            Some(ref answer) => answer.value(),
+            None => return Ok(None),
+        };
+        if self.strict_domain && res.is_nan() {
+            return Err(CalcrError {
+                desc: "Result is not a number".to_string(),
+                span: span,
+            });
+        } else if self.strict_domain && res.is_infinite() {
+            return Err(CalcrError {
+                desc: "Result is infinite".to_string(),
+                span: span,
+            });
+        }
         // if we got an actual number as the result, then store it for later use
-        if let Ok(Some(ref res)) = result {
-            self.last_result = *res;
+        if update_ans {
+            let prev = (self.last_result, self.ans_imaginary);
+            self.record_change(StateChange::LastResult(prev.0, prev.1));
+            self.last_result = res;
+            self.ans_imaginary = matches!(result, Some(Answer::Imaginary(_)));
+            self.update_last_result_var();
+        }
+        Ok(result)
+    }
+
+    /// Makes `LAST_RESULT_VAR` hold the last result, unless it is imaginary
+    fn update_last_result_var(&mut self) {
+        if self.ans_imaginary {
+            self.vars.remove(LAST_RESULT_VAR);
+        } else {
+            self.vars.insert(LAST_RESULT_VAR.to_string(), self.last_result);
+        }
+    }
+
+    /// Evaluates `cmd` if it is an interpreter command rather than an expression
+    ///
+    /// Returns `None` if `cmd` is not a command, in which case it should be evaluated as an
+    /// expression instead.
+    fn eval_command(&mut self, expr: &str) -> Option<CalcrResult<Option<Answer>>> {
+        let cmd = expr.trim();
+        if cmd.starts_with('#') {
+            return Some(self.eval_tagged_result(expr, &cmd[1..]));
+        }
+        let name = match cmd.split_whitespace().next() {
+            Some(name) => name,
+            None => return None,
+        };
+        let arg = cmd[name.len()..].trim();
+        match name {
+            "reset" if arg.is_empty() => {
+                let vars = mem::replace(&mut self.vars, HashMap::new());
+                let last_result = mem::replace(&mut self.last_result, 0.0);
+                let ans_imaginary = mem::replace(&mut self.ans_imaginary, false);
+                self.record_change(StateChange::Reset(vars, last_result, ans_imaginary));
+                Some(Ok(None))
+            },
+            "undo" if arg.is_empty() => Some(self.undo()),
+            // a command name followed by e.g. `= 2` is instead used as a variable
+            "precision" if self.is_command_arg(arg) => {
+                Some(self.eval_precision_cmd(expr, arg).map(|text| self.show(text)))
+            },
+            "show" if self.is_command_arg(arg) => {
+                Some(self.eval_show_cmd(expr, arg).map(|text| self.show(text)))
+            },
+            "explain" if self.is_command_arg(arg) => {
+                Some(self.eval_explain_cmd(expr, arg).map(|text| self.show(Some(text))))
+            },
+            "angle" if self.is_command_arg(arg) => {
+                Some(self.eval_angle_cmd(expr, arg).map(|text| self.show(text)))
+            },
+            "help" if self.is_command_arg(arg) => {
+                Some(eval_help_cmd(expr, arg).map(|text| self.show(Some(text))))
+            },
+            "copy" if self.is_command_arg(arg) => Some(self.eval_copy_cmd(expr, arg)),
+            "results" if arg.is_empty() => {
+                let text = if self.results.is_empty() {
+                    "No results have been named yet".to_string()
+                } else {
+                    self.list_results().join("\n")
+                };
+                Some(Ok(self.show(Some(text))))
+            },
+            _ => None,
         }
+    }
+
+    /// Whether `arg` is the argument of the command before it, rather than continuing an
+    /// expression which uses the command name as a variable, e.g. `= 2` or `* 2`
+    fn is_command_arg(&self, arg: &str) -> bool {
+        match lex_equation_with_options(&arg.to_string(), self.lexer_options) {
+            Ok(toks) => match toks.first() {
+                Some(tok) => tok.val != TokVal::Op(TokOp::Assign) && !continues_last_result(&toks),
+                None => true,
+            },
+            // the command reports the error
+            Err(_) => true,
+        }
+    }
+
+    /// Keeps `text`, if any, as the output of the current command, which has no result
+    fn show(&mut self, text: Option<String>) -> Option<Answer> {
+        self.command_output = text;
+        None
+    }
+
+    /// Returns the text shown by the most recent command, such as the current precision for
+    /// `precision` or the steps of `explain`
+    ///
+    /// This is `None` if the most recent expression was not a command, or did not show anything.
+    pub fn command_output(&self) -> Option<&str> {
+        self.command_output.as_deref()
+    }
+
+    /// Reverts the most recent change to the variables or the last result
+    fn undo(&mut self) -> CalcrResult<Option<Answer>> {
+        match self.undo_stack.pop() {
+            Some(change) => self.revert(change),
+            None => return Err(CalcrError {
+                desc: "Nothing to undo".to_string(),
+                span: None,
+            }),
+        }
+        Ok(None)
+    }
+
+    /// Puts back the state from before `change`
+    fn revert(&mut self, change: StateChange) {
+        match change {
+            StateChange::Var(name, Some(val)) => {
+                self.vars.insert(name, val);
+            },
+            StateChange::Var(name, None) => {
+                self.vars.remove(&name);
+            },
+            StateChange::Vars(prevs) => {
+                // in reverse, so a name assigned twice gets the value from before both
+                for (name, prev) in prevs.into_iter().rev() {
+                    match prev {
+                        Some(val) => self.vars.insert(name, val),
+                        None => self.vars.remove(&name),
+                    };
+                }
+            },
+            StateChange::LastResult(val, imaginary) => {
+                self.last_result = val;
+                self.ans_imaginary = imaginary;
+                self.update_last_result_var();
+            },
+            StateChange::Reset(vars, last_result, ans_imaginary) => {
+                self.vars = vars;
+                self.last_result = last_result;
+                self.ans_imaginary = ans_imaginary;
+            },
+            StateChange::NamedResult(name, Some(val)) => {
+                self.results.insert(name, val);
+            },
+            StateChange::NamedResult(name, None) => {
+                self.results.remove(&name);
+            },
+            StateChange::Several(changes) => {
+                for change in changes.into_iter().rev() {
+                    self.revert(change);
+                }
+            },
+        }
+    }
+
+    /// Remembers `change` so it can be undone later, forgetting the oldest change if needed
+    fn record_change(&mut self, change: StateChange) {
+        if self.undo_stack.len() >= MAX_UNDO {
+            self.undo_stack.remove(0);
+        }
+        self.undo_stack.push(change);
+    }
+
+    /// Shows the current output precision if `arg` is empty, and otherwise sets it to `arg`
+    fn eval_precision_cmd(&mut self, expr: &str, arg: &str) -> CalcrResult<Option<String>> {
+        if arg.is_empty() {
+            Ok(Some(match (self.format.sig_figs, self.format.precision) {
+                (Some(sig_figs), _) => format!("Precision: {} significant figures", sig_figs),
+                (None, Some(precision)) => format!("Precision: {} decimal places", precision),
+                (None, None) => "Precision: full".to_string(),
+            }))
+        } else if let Ok(precision) = arg.parse::<usize>() {
+            self.format.precision = Some(precision);
+            self.format.sig_figs = None;
+            Ok(None)
+        } else {
+            Err(CalcrError {
+                desc: "Precision must be a non-negative whole number".to_string(),
+                span: Some(char_span(expr, arg)),
+            })
+        }
+    }
+
+    /// Sets the value of `ans` and `_`, e.g. to continue a calculation from an earlier session
+    pub fn set_last_result(&mut self, val: f64) {
+        self.last_result = val;
+        self.ans_imaginary = false;
+        self.update_last_result_var();
+    }
+
+    /// Writes the variables as `name = value` lines, which are sorted by name and followed by
+    /// the value of `ans` on a line of its own, such that `load_state` can restore them later
+    ///
+    /// An imaginary `ans` is left out, since it cannot be written as a number.
+    pub fn save_state(&self) -> String {
+        let mut names: Vec<&String> = self.vars.keys()
+                                               .filter(|name| *name != LAST_RESULT_VAR)
+                                               .collect();
+        names.sort();
+        let mut state = String::new();
+        for name in names {
+            state.push_str(&format!("{} = {}\n", name, state_number(self.vars[name])));
+        }
+        if !self.ans_imaginary {
+            state.push_str(&format!("{}\n", state_number(self.last_result)));
+        }
+        state
+    }
+
+    /// Restores the variables and `ans` from the output of `save_state`, by evaluating each of
+    /// its lines
+    ///
+    /// The lines are always read as infix notation with the default lexer options, regardless of
+    /// the current settings, and NaN or infinite values are restored even with `strict_domain`.
+    /// Variables which are not in `state` are kept.
+    pub fn load_state(&mut self, state: &str) -> CalcrResult<()> {
+        let options = mem::take(&mut self.lexer_options);
+        let rpn = mem::replace(&mut self.rpn, false);
+        let strict_domain = mem::replace(&mut self.strict_domain, false);
+        let mut result = Ok(());
+        for line in state.lines().filter(|line| !line.trim().is_empty()) {
+            if let Err(err) = self.eval_expression(&line.to_string()) {
+                result = Err(err);
+                break;
+            }
+        }
+        self.lexer_options = options;
+        self.rpn = rpn;
+        self.strict_domain = strict_domain;
         result
     }
 
-    fn eval_expr(&mut self, ast: &Ast) -> CalcrResult<Option<f64>> {
+    /// Replaces the options used when lexing expressions
+    pub fn set_lexer_options(&mut self, options: LexerOptions) {
+        self.lexer_options = options;
+    }
+
+    /// Sets whether expressions are read in reverse Polish notation rather than infix notation
+    pub fn set_rpn(&mut self, rpn: bool) {
+        self.rpn = rpn;
+    }
+
+    /// Sets whether whole numbers and fractions of them are computed exactly, such that e.g.
+    /// `1/3 + 1/3` results in exactly 2/3, which is then shown as a fraction
+    pub fn set_exact(&mut self, exact: bool) {
+        self.exact = exact;
+    }
+
+    /// Sets whether results which are NaN or infinite, such as `0/0` or `1/0`, are errors
+    pub fn set_strict_domain(&mut self, strict: bool) {
+        self.strict_domain = strict;
+    }
+
+    /// Sets whether the square root of a negative number is allowed as the final result, instead
+    /// of being an error like anywhere else
+    ///
+    /// Since only the magnitude of such a result is returned, e.g. 2 for `sqrt(-4)`, this should
+    /// only be enabled when the results are shown with `format_answer`, which adds an `i` suffix.
+    pub fn set_imaginary_results(&mut self, allow: bool) {
+        self.imaginary_results = allow;
+    }
+
+    /// Converts `num` to a `Number`, which is exact if possible and enabled
+    fn number(&self, num: f64) -> Number {
+        if self.exact { Number::from_f64(num) } else { Number::Float(num) }
+    }
+
+    /// Sets whether the physical constants, such as `c` for the speed of light, can be used
+    pub fn set_physics(&mut self, physics: bool) {
+        self.physics = physics;
+    }
+
+    /// Returns the value of the physical constant called `name`, if they are enabled
+    fn physics_constant(&self, name: &str) -> Option<f64> {
+        if self.physics {
+            PHYSICS_CONSTANTS.iter().find(|&&(c, _)| c == name).map(|&(_, val)| val)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the names of each constant which can be used, where the first is the main one,
+    /// along with its value
+    pub fn constants(&mut self) -> Vec<(Vec<&'static str>, f64)> {
+        let mut constants = Vec::new();
+        for builtin in BUILTINS.iter() {
+            if let Const(ref c) = builtin.val {
+                if let Ok(val) = self.eval_const(c) {
+                    constants.push((builtin.names.to_vec(), val));
+                }
+            }
+        }
+        if self.physics {
+            constants.extend(PHYSICS_CONSTANTS.iter().map(|&(name, val)| (vec!(name), val)));
+        }
+        constants
+    }
+
+    /// Sets the largest number the factorial function accepts, to avoid huge computations
+    pub fn set_max_factorial(&mut self, limit: u64) {
+        self.max_factorial = limit;
+    }
+
+    /// Replaces the settings used by `format_result`
+    pub fn set_format(&mut self, format: OutputFormat) {
+        self.format = format;
+    }
+
+    /// Shows the current display mode if `arg` is empty, and otherwise sets it to `arg`
+    fn eval_show_cmd(&mut self, expr: &str, arg: &str) -> CalcrResult<Option<String>> {
+        self.format.mode = match arg {
+            "" => return Ok(Some(format!("Showing results as {}", self.format.mode.name()))),
+            "decimal" => DisplayMode::Decimal,
+            "fraction" => DisplayMode::Fraction,
+            "percent" => DisplayMode::Percent,
+            _ => return Err(CalcrError {
+                desc: "Expected decimal, fraction or percent".to_string(),
+                span: Some(char_span(expr, arg)),
+            }),
+        };
+        Ok(None)
+    }
+
+    /// Evaluates the expression `arg` like any other, and places the shown result on the clipboard
+    fn eval_copy_cmd(&mut self, expr: &str, arg: &str) -> CalcrResult<Option<Answer>> {
+        let ast = try!(self.parse_part(expr, arg));
+        let result = try!(self.eval_result(&ast, true, Some(char_span(expr, arg))));
+        if let Some(ref answer) = result {
+            let shown = self.format_answer(answer);
+            try!((self.clipboard)(&shown));
+        }
+        Ok(result)
+    }
+
+    /// Sets how the `copy` command places text on the clipboard, which by default always fails
+    /// with the error `No clipboard is configured`
+    pub fn set_clipboard<F>(&mut self, copy: F) where F: Fn(&str) -> CalcrResult<()> + 'static {
+        self.clipboard = Box::new(copy);
+    }
+
+    /// Shows the steps taken to evaluate the expression `arg`
+    fn eval_explain_cmd(&mut self, expr: &str, arg: &str) -> CalcrResult<String> {
+        self.explain(expr, arg).map(|steps| steps.join("\n"))
+    }
+
+    /// Evaluates the expression `arg`, returning each intermediate calculation as a line of text
+    ///
+    /// `arg` must be a slice of `expr`, so that the spans of any errors point into `expr`.
+    fn explain(&mut self, expr: &str, arg: &str) -> CalcrResult<Vec<String>> {
+        let ast = try!(self.parse_part(expr, arg));
+        if ast.val == Op(Assign) {
+            return Err(CalcrError {
+                desc: "Cannot explain an assignment".to_string(),
+                span: Some(ast.span),
+            });
+        }
+        let mut steps = Vec::new();
+        let val = try!(self.explain_eq(&ast, &mut steps));
+        if steps.is_empty() {
+            let written = ast.written(self.lexer_options);
+            steps.push(format!("{} = {}", written, self.format_result(val)));
+        }
+        Ok(steps)
+    }
+
+    /// Evaluates `#name = EXPR`, storing the result both as a variable and as a named result
+    ///
+    /// `tagged` is the part of `expr` following the `#`.
+    fn eval_tagged_result(&mut self, expr: &str, tagged: &str) -> CalcrResult<Option<Answer>> {
+        // parsed as an assignment, so the name is checked like that of any other variable
+        let ast = try!(self.parse_part(expr, tagged));
+        if ast.val != Op(Assign) {
+            return Err(CalcrError {
+                desc: "Expected `=` after the result name".to_string(),
+                span: Some(char_span(expr, tagged)),
+            });
+        }
+        if ast.branches.len() != 2 {
+            return Err(CalcrError {
+                desc: "Only one result can be named at a time".to_string(),
+                span: Some(ast.get_total_span()),
+            });
+        }
+        let name = try!(self.assign_name(&ast.branches[0]));
+        let rhs = &ast.branches[1];
+        if rhs.val == Op(Assign) {
+            return Err(CalcrError {
+                desc: "Cannot name the result of an assignment".to_string(),
+                span: Some(rhs.span),
+            });
+        }
+        let answer = match try!(self.eval_result(rhs, false, Some(rhs.get_total_span()))) {
+            Some(Answer::Imaginary(_)) => return Err(CalcrError {
+                desc: "Complex arithmetic is not supported".to_string(),
+                span: Some(rhs.get_total_span()),
+            }),
+            Some(answer) => answer,
+            // only assignments have no result, and those were ruled out above
+            None => return Ok(None),
+        };
+        let val = answer.value();
+        let changes = vec!(
+            StateChange::LastResult(self.last_result, self.ans_imaginary),
+            StateChange::Var(name.clone(), self.vars.insert(name.clone(), val)),
+            StateChange::NamedResult(name.clone(), self.results.insert(name, val)),
+        );
+        self.last_result = val;
+        self.ans_imaginary = false;
+        self.update_last_result_var();
+        self.record_change(StateChange::Several(changes));
+        Ok(Some(answer))
+    }
+
+    /// Returns a line for each named result, in alphabetical order
+    fn list_results(&self) -> Vec<String> {
+        self.results.iter().map(|(name, val)| {
+            format!("#{} = {}", name, self.format_result(*val))
+        }).collect()
+    }
+
+    /// Lexes and parses `part` on its own
+    ///
+    /// `part` must be a slice of `expr`, so that the spans of any errors point into `expr`.
+    fn parse_part(&self, expr: &str, part: &str) -> CalcrResult<Ast> {
+        // pad the expression so the spans of the tokens line up with the full input
+        let padded = " ".repeat(char_span(expr, part).0) + part;
+        let toks = try!(lex_equation_with_options(&padded, self.lexer_options));
+        self.parse_infix(toks)
+    }
+
+    /// Parses `toks` as a normal infix expression, which may call the registered functions
+    fn parse_infix(&self, toks: Vec<Token>) -> CalcrResult<Ast> {
+        parse_tokens_with_functions(toks, self.functions.keys().cloned().collect())
+    }
+
+    /// Makes `func` callable as `name(x, ...)` in expressions, taking exactly `arity` arguments
+    ///
+    /// Fails if `name` is already a builtin function, constant or operator. Registering a
+    /// function under the name of another registered function replaces the old function.
+    pub fn register_fn<F>(&mut self, name: &str, arity: usize, func: F) -> CalcrResult<()>
+        where F: Fn(&[f64]) -> CalcrResult<f64> + 'static {
+        if is_reserved_name(name) {
+            return Err(CalcrError {
+                desc: format!("Cannot register a function named {}, which is builtin", name),
+                span: None,
+            });
+        }
+        self.functions.insert(name.to_string(), ExternFn { arity: arity, func: Box::new(func) });
+        Ok(())
+    }
+
+    /// Evaluates `ast` like `eval_eq`, while pushing a description of each step onto `steps`
+    fn explain_eq(&mut self, ast: &Ast, steps: &mut Vec<String>) -> CalcrResult<f64> {
+        // logical operators are explained as a single step, so they still short-circuit
+        if ast.branches.is_empty() || ast.val == Op(And) || ast.val == Op(Or) {
+            let val = try!(self.eval_eq(ast));
+            if !ast.branches.is_empty() {
+                let written = ast.written(self.lexer_options);
+                steps.push(format!("{} = {}", written, self.format_result(val)));
+            }
+            return Ok(val);
+        }
+        if self.depth >= MAX_EVAL_DEPTH {
+            return Err(CalcrError {
+                desc: "Expression too deeply nested".to_string(),
+                span: Some(ast.span),
+            });
+        }
+        self.depth += 1;
+        let mut branches = Vec::new();
+        for branch in ast.branches.iter() {
+            match self.explain_eq(branch, steps) {
+                Ok(val) => branches.push(Ast {
+                    val: Num(val),
+                    span: branch.get_total_span(),
+                    branches: Vec::new(),
+                }),
+                Err(e) => {
+                    self.depth -= 1;
+                    return Err(e);
+                },
+            }
+        }
+        self.depth -= 1;
+        let step = Ast { val: ast.val.clone(), span: ast.span, branches: branches };
+        let val = try!(self.eval_eq(&step));
+        let written = step.written(self.lexer_options);
+        steps.push(format!("{} = {}", written, self.format_result(val)));
+        Ok(val)
+    }
+
+    /// Shows the current angle mode if `arg` is empty, and otherwise sets it to `arg`
+    fn eval_angle_cmd(&mut self, expr: &str, arg: &str) -> CalcrResult<Option<String>> {
+        self.angle_mode = match arg {
+            "" => return Ok(Some(match self.angle_mode {
+                AngleMode::Radians => "Angles are in radians".to_string(),
+                AngleMode::Degrees => "Angles are in degrees".to_string(),
+            })),
+            "rad" => AngleMode::Radians,
+            "deg" => AngleMode::Degrees,
+            _ => return Err(CalcrError {
+                desc: "Expected rad or deg".to_string(),
+                span: Some(char_span(expr, arg)),
+            }),
+        };
+        Ok(None)
+    }
+
+    /// Returns the unit currently used for angles
+    pub fn angle_mode(&self) -> AngleMode {
+        self.angle_mode
+    }
+
+    /// Converts `angle` from the current angle mode to radians
+    fn to_radians(&self, angle: f64) -> f64 {
+        match self.angle_mode {
+            AngleMode::Radians => angle,
+            AngleMode::Degrees => angle.to_radians(),
+        }
+    }
+
+    /// Converts `angle` from radians to the current angle mode
+    fn from_radians(&self, angle: f64) -> f64 {
+        match self.angle_mode {
+            AngleMode::Radians => angle,
+            AngleMode::Degrees => angle.to_degrees(),
+        }
+    }
+
+    /// Formats `num` for display, using the current output settings
+    pub fn format_result(&self, num: f64) -> String {
+        self.format.format(num)
+    }
+
+    /// Formats `answer`, marking it with an `i` if it is imaginary, or in another base if it was
+    /// given by `tobase`
+    ///
+    /// The solution given by `solve` is shown along with the unknown, e.g. `x = 2`.
+    pub fn format_answer(&self, answer: &Answer) -> String {
+        match *answer {
+            Answer::Number(num, fraction) => self.format_number(num, fraction),
+            Answer::Bool(num) if self.format.bool_words => {
+                (if num != 0.0 { "true" } else { "false" }).to_string()
+            },
+            Answer::Bool(num) => self.format_result(num),
+            Answer::Imaginary(num) => format!("{}i", self.format_result(num)),
+            Answer::InBase(num, base) => format_in_base(num, base),
+            Answer::ContFrac(_, ref coeffs) => {
+                let rest: Vec<String> = coeffs[1..].iter().map(|coeff| coeff.to_string()).collect();
+                if rest.is_empty() {
+                    format!("[{}]", coeffs[0])
+                } else {
+                    format!("[{}; {}]", coeffs[0], rest.join(", "))
+                }
+            },
+            Answer::Solution(ref name, num, fraction) => {
+                format!("{} = {}", name, self.format_number(num, fraction))
+            },
+        }
+    }
+
+    /// Formats `num` as its exact `fraction` if it has one and fractions are shown
+    fn format_number(&self, num: f64, fraction: Option<(i64, i64)>) -> String {
+        match fraction {
+            Some((numer, denom)) if self.shows_fractions() => format!("{}/{}", numer, denom),
+            _ => self.format_result(num),
+        }
+    }
+
+    /// Whether exact fractions are shown as such, which is only the case if the output format
+    /// doesn't ask for something else
+    fn shows_fractions(&self) -> bool {
+        self.format.mode == DisplayMode::Decimal && self.format.precision.is_none() &&
+            self.format.sig_figs.is_none() && !self.format.hex_float
+    }
+
+    /// Evaluates `ast`, returning the result (if any) tagged with how it should be shown
+    fn eval_expr(&mut self, ast: &Ast) -> CalcrResult<Option<Answer>> {
         if ast.val == Op(Assign) {
-            let (lhs, rhs) = try!(ast.get_binary_branches());
-            if let Name(ref name) = lhs.val {
-                let val = try!(self.eval_eq(rhs));
-                self.vars.insert(name.clone(), val);
-                Ok(None)
+            try!(self.eval_assign(ast));
+            Ok(None)
+        } else if ast.val == Func(Sqrt) && ast.branches.len() == 1 && self.imaginary_results {
+            // the square root of a negative number is allowed as the final result, since it
+            // can be shown as an imaginary number even though we can't calculate with it
+            let arg = try!(self.eval_eq(&ast.branches[0]));
+            if arg < 0.0 {
+                Ok(Some(Answer::Imaginary((-arg).sqrt())))
             } else {
-                Err(CalcrError {
-                    desc: "Interal error - expected Assign to have Name in left branch"
-                          .to_string(),
-                    span: None,
-                })
+                Ok(Some(Answer::Number(arg.sqrt(), None)))
+            }
+        } else if ast.val == Func(Solve) {
+            let (name, num) = try!(self.eval_solve(ast));
+            Ok(Some(Answer::Solution(name, num.to_f64(), num.fraction())))
+        } else if ast.val == Func(ToBase) {
+            // the result is still a plain number, which is only shown in the base
+            let (num, base) = try!(self.eval_to_base(ast));
+            Ok(Some(Answer::InBase(num, base)))
+        } else if ast.val == Func(ContFrac) {
+            // the result is the value of the shown continued fraction
+            let coeffs = try!(self.eval_cont_frac(ast));
+            let mut num = self.number(coeffs[coeffs.len() - 1] as f64);
+            for &coeff in coeffs[..coeffs.len() - 1].iter().rev() {
+                num = self.number(coeff as f64).add(&self.number(1.0).div(&num));
+            }
+            Ok(Some(Answer::ContFrac(num.to_f64(), coeffs)))
+        } else {
+            let num = try!(self.eval_number(ast));
+            match ast.val {
+                Op(ref op) if op.is_boolean() => Ok(Some(Answer::Bool(num.to_f64()))),
+                _ => Ok(Some(Answer::Number(num.to_f64(), num.fraction()))),
             }
+        }
+    }
+
+    /// Evaluates the arguments of `tobase`, returning the number and the base
+    fn eval_to_base(&mut self, ast: &Ast) -> CalcrResult<(f64, u32)> {
+        if ast.branches.len() != 2 {
+            return Err(CalcrError {
+                desc: "Function takes exactly two arguments".to_string(),
+                span: Some(ast.get_total_span()),
+            });
+        }
+        let num = try!(self.eval_eq(&ast.branches[0]));
+        if num.fract() != 0.0 || num.abs() >= u64::max_value() as f64 {
+            return Err(CalcrError {
+                desc: "Only whole numbers can be shown in another base".to_string(),
+                span: Some(ast.branches[0].get_total_span()),
+            });
+        }
+        let base = try!(self.eval_eq(&ast.branches[1]));
+        if base.fract() != 0.0 || base < 2.0 || base > 36.0 {
+            return Err(CalcrError {
+                desc: "The base must be a whole number from 2 to 36".to_string(),
+                span: Some(ast.branches[1].get_total_span()),
+            });
+        }
+        Ok((num, base as u32))
+    }
+
+    /// Evaluates an assignment, which may assign several variables at once, e.g. `a, b = 3, 4`
+    ///
+    /// The first half of the branches are the names, and the second half are the values. All the
+    /// values are evaluated before any variable is assigned, so `a, b = b, a` swaps the two.
+    fn eval_assign(&mut self, ast: &Ast) -> CalcrResult<()> {
+        let (targets, values) = ast.branches.split_at(ast.branches.len() / 2);
+        let mut names = Vec::with_capacity(targets.len());
+        for target in targets {
+            names.push(try!(self.assign_name(target)));
+        }
+        let mut vals = Vec::with_capacity(values.len());
+        for value in values {
+            vals.push(try!(self.eval_eq(value)));
+        }
+        let mut prevs: Vec<_> = names.into_iter().zip(vals).map(|(name, val)| {
+            let prev = self.vars.insert(name.clone(), val);
+            (name, prev)
+        }).collect();
+        if prevs.len() == 1 {
+            let (name, prev) = prevs.pop().unwrap();
+            self.record_change(StateChange::Var(name, prev));
         } else {
-            self.eval_eq(ast).map(|val| Some(val))
+            self.record_change(StateChange::Vars(prevs));
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the variable assigned to by `target`, if it can be assigned
+    fn assign_name(&self, target: &Ast) -> CalcrResult<String> {
+        let name = match target.val {
+            Name(ref name) => name.clone(),
+            _ => return Err(CalcrError {
+                desc: "Interal error - expected Assign to have Name in left branch".to_string(),
+                span: None,
+            }),
+        };
+        if name == LAST_RESULT_VAR {
+            return Err(CalcrError {
+                desc: format!("`{}` always holds the last result and cannot be assigned", name),
+                span: Some(target.span),
+            });
+        }
+        if self.physics_constant(&name).is_some() {
+            return Err(CalcrError {
+                desc: format!("`{}` is a physical constant and cannot be reassigned", name),
+                span: Some(target.span),
+            });
+        }
+        Ok(name)
+    }
+
+    /// Solves the linear equation given to `solve`, returning the unknown and its value
+    fn eval_solve(&mut self, ast: &Ast) -> CalcrResult<(String, Number)> {
+        if ast.branches.len() != 2 {
+            return Err(CalcrError {
+                desc: "Function takes exactly two arguments".to_string(),
+                span: Some(ast.get_total_span()),
+            });
+        }
+        let (eq, unknown) = (&ast.branches[0], &ast.branches[1]);
+        let name = match unknown.val {
+            Name(ref name) => name.clone(),
+            _ => return Err(CalcrError {
+                desc: "Expected the name of the unknown".to_string(),
+                span: Some(unknown.get_total_span()),
+            }),
+        };
+        if eq.val != Op(Assign) && eq.val != Op(Equal) {
+            return Err(CalcrError {
+                desc: "Expected an equation, e.g. 2*x + 3 = 7".to_string(),
+                span: Some(eq.get_total_span()),
+            });
+        }
+        // moving everything to the left hand side gives `coef * x + constant = 0`
+        let (lhs, rhs) = try!(eq.get_binary_branches());
+        let unknowns = nodes_containing(eq, &name);
+        let (lhs_coef, lhs_const) = try!(self.eval_linear(lhs, &unknowns));
+        let (rhs_coef, rhs_const) = try!(self.eval_linear(rhs, &unknowns));
+        let (coef, constant) = (lhs_coef.sub(&rhs_coef), lhs_const.sub(&rhs_const));
+        if coef.to_f64() == 0.0 {
+            let desc = if constant.to_f64() == 0.0 {
+                format!("Every value of {} solves the equation", name)
+            } else {
+                "The equation has no solution".to_string()
+            };
+            return Err(CalcrError {
+                desc: desc,
+                span: Some(eq.get_total_span()),
+            });
+        }
+        Ok((name, constant.neg().div(&coef)))
+    }
+
+    /// Evaluates `ast` as `coef * unknown + constant`, returning the coefficient and the constant,
+    /// where `unknowns` are the nodes which contain the unknown
+    ///
+    /// Fails if `ast` is not linear in the unknown, e.g. `x^2` or `x * x`.
+    fn eval_linear(&mut self, ast: &Ast, unknowns: &HashSet<*const Ast>)
+        -> CalcrResult<(Number, Number)> {
+        if !unknowns.contains(&(ast as *const Ast)) {
+            return Ok((self.number(0.0), try!(self.eval_number(ast))));
         }
+        try!(self.check_limits(ast));
+        self.depth += 1;
+        let result = self.eval_linear_node(ast, unknowns);
+        self.depth -= 1;
+        result
+    }
+
+    fn eval_linear_node(&mut self, ast: &Ast, unknowns: &HashSet<*const Ast>)
+        -> CalcrResult<(Number, Number)> {
+        let linear = match (&ast.val, ast.branches.len()) {
+            (&Name(_), 0) | (&CalledName(_), 0) => Some((self.number(1.0), self.number(0.0))),
+            (&Op(Neg), 1) => {
+                let (coef, constant) = try!(self.eval_linear(&ast.branches[0], unknowns));
+                Some((coef.neg(), constant.neg()))
+            },
+            (&Op(_), 2) => return self.eval_linear_chain(ast, unknowns),
+            _ => None,
+        };
+        linear.ok_or_else(|| nonlinear(ast))
+    }
+
+    /// Evaluates a binary operator like `eval_linear`, along with any chain it ends, such as
+    /// `x + 2 - 3x`, looping over the chain instead of recursing into it
+    fn eval_linear_chain(&mut self, ast: &Ast, unknowns: &HashSet<*const Ast>)
+        -> CalcrResult<(Number, Number)> {
+        let mut chain = vec!(ast);
+        let mut node = ast;
+        while let Op(ref op) = node.val {
+            let lhs = &node.branches[0];
+            if lhs.branches.len() != 2 || !is_chained(op, &lhs.val) {
+                break;
+            }
+            chain.push(lhs);
+            node = lhs;
+        }
+        let (mut coef, mut constant) = try!(self.eval_linear(&node.branches[0], unknowns));
+        for node in chain.into_iter().rev() {
+            try!(self.check_limits(node));
+            let (rhs_coef, rhs_const) = try!(self.eval_linear(&node.branches[1], unknowns));
+            let (lhs_linear, rhs_linear) = (coef.to_f64() != 0.0, rhs_coef.to_f64() != 0.0);
+            let linear = match node.val {
+                Op(Plus) => Some((coef.add(&rhs_coef), constant.add(&rhs_const))),
+                Op(Minus) => Some((coef.sub(&rhs_coef), constant.sub(&rhs_const))),
+                Op(Mult) | Op(Of) if !(lhs_linear && rhs_linear) => {
+                    let coef = coef.mul(&rhs_const).add(&rhs_coef.mul(&constant));
+                    Some((coef, constant.mul(&rhs_const)))
+                },
+                Op(Div) if !rhs_linear => {
+                    Some((coef.div(&rhs_const), constant.div(&rhs_const)))
+                },
+                _ => None,
+            };
+            match linear {
+                Some(linear) => {
+                    coef = linear.0;
+                    constant = linear.1;
+                },
+                None => return Err(nonlinear(node)),
+            }
+        }
+        Ok((coef, constant))
+    }
+
+    /// Evaluates the arguments of `cf`, returning the coefficients of the continued fraction
+    fn eval_cont_frac(&mut self, ast: &Ast) -> CalcrResult<Vec<i64>> {
+        if ast.branches.len() != 2 {
+            return Err(CalcrError {
+                desc: "Function takes exactly two arguments".to_string(),
+                span: Some(ast.get_total_span()),
+            });
+        }
+        let num = try!(self.eval_number(&ast.branches[0]));
+        if !num.to_f64().is_finite() || num.to_f64().abs() >= i64::max_value() as f64 {
+            return Err(CalcrError {
+                desc: "Cannot find the continued fraction of this number".to_string(),
+                span: Some(ast.branches[0].get_total_span()),
+            });
+        }
+        let count = try!(self.eval_eq(&ast.branches[1]));
+        if count.fract() != 0.0 || count < 1.0 || count > MAX_CONT_FRAC_COEFFS {
+            return Err(CalcrError {
+                desc: format!("The number of coefficients must be a whole number from 1 to {}",
+                              MAX_CONT_FRAC_COEFFS),
+                span: Some(ast.branches[1].get_total_span()),
+            });
+        }
+        Ok(continued_fraction(num, count as usize))
     }
 
     fn eval_eq(&mut self, ast: &Ast) -> CalcrResult<f64> {
+        self.eval_number(ast).map(|num| num.to_f64())
+    }
+
+    /// Evaluates `ast` like `eval_eq`, but keeps the result exact if possible
+    fn eval_number(&mut self, ast: &Ast) -> CalcrResult<Number> {
+        try!(self.check_limits(ast));
+        self.depth += 1;
+        let result = self.eval_node(ast);
+        self.depth -= 1;
+        result
+    }
+
+    /// Fails if evaluating `ast` would recurse too deeply, or the deadline has passed
+    fn check_limits(&self, ast: &Ast) -> CalcrResult<()> {
+        if self.depth >= MAX_EVAL_DEPTH {
+            return Err(CalcrError {
+                desc: "Expression too deeply nested".to_string(),
+                span: Some(ast.span),
+            });
+        }
+        self.check_deadline()
+    }
+
+    /// Fails if the deadline set by `eval_with_timeout` has passed
+    fn check_deadline(&self) -> CalcrResult<()> {
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(CalcrError {
+                desc: "Evaluation timed out".to_string(),
+                span: None,
+            });
+        }
+        Ok(())
+    }
+
+    fn eval_node(&mut self, ast: &Ast) -> CalcrResult<Number> {
         match ast.val {
-            Func(ref f) => self.eval_func(f, ast),
             Op(ref o) => self.eval_op(o, ast),
+            _ => self.eval_value(ast).map(|num| self.number(num)),
+        }
+    }
+
+    /// Evaluates any node which is not an operator
+    fn eval_value(&mut self, ast: &Ast) -> CalcrResult<f64> {
+        match ast.val {
+            Func(ref f) => self.eval_func(f, ast),
+            Op(_) => self.eval_eq(ast),
             Const(ref c) => self.eval_const(c),
             Num(ref n) => Ok(*n),
+            LastResult if self.ans_imaginary => Err(CalcrError {
+                desc: "Complex arithmetic is not supported".to_string(),
+                span: Some(ast.span),
+            }),
             LastResult => Ok(self.last_result),
-            Name(ref name) => {
-                if let Some(val) = self.vars.get(name) {
+            PrevResult(n) => {
+                if n >= 1 && n <= self.batch_results.len() {
+                    Ok(self.batch_results[self.batch_results.len() - n])
+                } else {
+                    Err(CalcrError {
+                        desc: format!("There is no result {} expressions back in this batch", n),
+                        span: Some(ast.span),
+                    })
+                }
+            },
+            Call(ref name) => self.eval_call(name, ast),
+            Name(ref name) | CalledName(ref name) => {
+                if let Some(val) = self.physics_constant(name) {
+                    Ok(val)
+                } else if let Some(val) = self.vars.get(name) {
                     Ok(*val)
                 } else {
+                    let kind = if matches!(ast.val, CalledName(_)) {
+                        "Unknown function"
+                    } else {
+                        "Undefined variable"
+                    };
                     Err(CalcrError {
-                        desc: format!("Invalid function or constant: {}", name),
+                        desc: format!("{}: {}", kind, name),
                         span: Some(ast.get_total_span()),
                     })
                 }
-            }
-        }
-    }
-
-    fn eval_func(&mut self, f: &FuncKind, ast: &Ast) -> CalcrResult<f64> {
-        let child = try!(ast.get_unary_branch());
-        let arg = try!(self.eval_eq(child));
-        match *f {
-            Sin => Ok(arg.sin()),
-            Cos => Ok(arg.cos()),
-            Tan => Ok(arg.tan()),
-            Asin => Ok(arg.asin()),
-            Acos => Ok(arg.acos()),
-            Atan => Ok(arg.atan()),
-            Abs => Ok(arg.abs()),
-            Exp => Ok(arg.exp()),
+            }
+        }
+    }
+
+    /// Evaluates a call to the registered function `name`
+    fn eval_call(&mut self, name: &str, ast: &Ast) -> CalcrResult<f64> {
+        let arity = match self.functions.get(name) {
+            Some(extern_fn) => extern_fn.arity,
+            None => return Err(CalcrError {
+                desc: format!("Unknown function: {}", name),
+                span: Some(ast.span),
+            }),
+        };
+        if ast.branches.len() != arity {
+            return Err(CalcrError {
+                desc: format!("Function takes exactly {} argument(s)", arity),
+                span: Some(ast.get_total_span()),
+            });
+        }
+        let mut args = Vec::with_capacity(arity);
+        for branch in ast.branches.iter() {
+            args.push(try!(self.eval_eq(branch)));
+        }
+        (self.functions[name].func)(&args)
+    }
+
+    fn eval_func(&mut self, f: &FuncKind, ast: &Ast) -> CalcrResult<f64> {
+        match *f {
+            Stddev => self.eval_variance(ast).map(|var| var.sqrt()),
+            Variance => self.eval_variance(ast),
+            Hypot | Dist | Snap => self.eval_binary_func(f, ast),
+            Log if ast.branches.len() == 2 => self.eval_binary_func(f, ast),
+            Log if ast.branches.len() != 1 => Err(CalcrError {
+                desc: "Function takes one or two arguments".to_string(),
+                span: Some(ast.get_total_span()),
+            }),
+            ToBase => Err(CalcrError {
+                desc: "tobase can only be used on a whole expression".to_string(),
+                span: Some(ast.span),
+            }),
+            Solve => self.eval_solve(ast).map(|(_, num)| num.to_f64()),
+            ContFrac => Err(CalcrError {
+                desc: "cf can only be used on a whole expression".to_string(),
+                span: Some(ast.span),
+            }),
+            _ => self.eval_unary_func(f, ast),
+        }
+    }
+
+    fn eval_binary_func(&mut self, f: &FuncKind, ast: &Ast) -> CalcrResult<f64> {
+        if ast.branches.len() != 2 {
+            return Err(CalcrError {
+                desc: "Function takes exactly two arguments".to_string(),
+                span: Some(ast.get_total_span()),
+            });
+        }
+        let x = try!(self.eval_eq(&ast.branches[0]));
+        let y = try!(self.eval_eq(&ast.branches[1]));
+        match *f {
+            // unlike sqrt(x^2 + y^2) this does not overflow for large x and y
+            Hypot => Ok(x.hypot(y)),
+            Dist => Ok((x - y).abs()),
+            Snap if y == 0.0 => Err(CalcrError {
+                desc: "The step to snap to cannot be zero".to_string(),
+                span: Some(ast.branches[1].get_total_span()),
+            }),
+            Snap => Ok((x / y).round() * y),
+            Log if y <= 0.0 || y == 1.0 => Err(CalcrError {
+                desc: "The base of a logarithm must be positive and not 1".to_string(),
+                span: Some(ast.branches[1].get_total_span()),
+            }),
+            Log if x <= 0.0 => Err(CalcrError {
+                desc: "Cannot take the logarithm of a non-positive number".to_string(),
+                span: Some(ast.branches[0].get_total_span()),
+            }),
+            // the common bases are exact for exact powers, e.g. log_10(1000) is 3
+            Log if y == 2.0 => Ok(x.log2()),
+            Log if y == 10.0 => Ok(x.log10()),
+            Log => Ok(x.log(y)),
+            _ => Err(CalcrError {
+                desc: "Internal error - expected binary function".to_string(),
+                span: None,
+            }),
+        }
+    }
+
+    /// Evaluates the reciprocal trigonometric function `f` as `1 / val`, where `val` is the
+    /// result of the function called `of` on the argument `arg`
+    fn eval_reciprocal(&self, f: &FuncKind, of: &str, val: f64, arg: &Ast)
+        -> CalcrResult<f64> {
+        // e.g. cos(pi/2) is only about 6e-17, since pi/2 itself is rounded
+        if val.abs() < RECIPROCAL_ZERO {
+            Err(CalcrError {
+                desc: format!("{} is undefined where {} is zero", f.name(), of),
+                span: Some(arg.get_total_span()),
+            })
+        } else {
+            Ok(1.0 / val)
+        }
+    }
+
+    fn eval_unary_func(&mut self, f: &FuncKind, ast: &Ast) -> CalcrResult<f64> {
+        if ast.branches.len() != 1 {
+            return Err(CalcrError {
+                desc: "Function takes exactly one argument".to_string(),
+                span: Some(ast.get_total_span()),
+            });
+        }
+        let child = try!(ast.get_unary_branch());
+        let arg = try!(self.eval_eq(child));
+        match *f {
+            Sin => Ok(self.to_radians(arg).sin()),
+            Cos => Ok(self.to_radians(arg).cos()),
+            Tan => Ok(self.to_radians(arg).tan()),
+            Sec => self.eval_reciprocal(f, "cos", self.to_radians(arg).cos(), child),
+            Csc => self.eval_reciprocal(f, "sin", self.to_radians(arg).sin(), child),
+            Cot => self.eval_reciprocal(f, "tan", self.to_radians(arg).tan(), child),
+            Asin => Ok(self.from_radians(arg.asin())),
+            Acos => Ok(self.from_radians(arg.acos())),
+            Atan => Ok(self.from_radians(arg.atan())),
+            Abs => Ok(arg.abs()),
+            Frac => Ok(arg.fract()),
+            IntPart => Ok(arg.trunc()),
+            Floor => Ok(arg.floor()),
+            Ceil => Ok(arg.ceil()),
+            Gamma => {
+                if arg <= 0.0 && arg.fract() == 0.0 {
+                    Err(CalcrError {
+                        desc: "The gamma function is undefined for non-positive whole numbers"
+                              .to_string(),
+                        span: Some(child.get_total_span()),
+                    })
+                } else {
+                    Ok(gamma(arg))
+                }
+            },
+            Exp => {
+                // underflowing to 0 for very negative arguments is close enough, so only the
+                // overflow to infinity is an error
+                let res = arg.exp();
+                if arg.is_finite() && !res.is_finite() {
+                    Err(CalcrError {
+                        desc: "exp overflowed".to_string(),
+                        span: Some(child.get_total_span()),
+                    })
+                } else {
+                    Ok(res)
+                }
+            },
             Sqrt => {
                 if arg < 0.0 {
                     Err(CalcrError {
-                        desc: "Cannot take the square root of a negative number".to_string(),
+                        desc: "Complex arithmetic is not supported".to_string(),
                         span: Some(child.get_total_span()),
                     })
                 } else {
@@ -114,32 +1460,58 @@ impl Interpreter {
                     Ok(arg.log10())
                 }
             },
+            _ => Err(CalcrError {
+                desc: "Internal error - expected unary function".to_string(),
+                span: None,
+            }),
+        }
+    }
+
+    /// Computes the population variance of the arguments of `ast`
+    fn eval_variance(&mut self, ast: &Ast) -> CalcrResult<f64> {
+        if ast.branches.len() < 2 {
+            return Err(CalcrError {
+                desc: "Function takes at least two arguments".to_string(),
+                span: Some(ast.get_total_span()),
+            });
+        }
+        let mut args = Vec::with_capacity(ast.branches.len());
+        for branch in ast.branches.iter() {
+            args.push(try!(self.eval_eq(branch)));
         }
+        let count = args.len() as f64;
+        let mean = args.iter().fold(0.0, |sum, x| sum + x) / count;
+        Ok(args.iter().fold(0.0, |sum, x| sum + (x - mean).powi(2)) / count)
     }
 
-    fn eval_op(&mut self, op: &OpKind, ast: &Ast) -> CalcrResult<f64> {
+    fn eval_op(&mut self, op: &OpKind, ast: &Ast) -> CalcrResult<Number> {
         match ast.branches.len() {
             2 => {
                 let (lhs, rhs) = ast.get_binary_branches().unwrap();
-                let (lhs, rhs) = (try!(self.eval_eq(lhs)), try!(self.eval_eq(rhs)));
-                match *op {
-                    Plus => Ok(lhs + rhs),
-                    Minus => Ok(lhs - rhs),
-                    Mult => Ok(lhs * rhs),
-                    Div => Ok(lhs / rhs),
-                    Pow => Ok(lhs.powf(rhs)),
-                    _ => Err(CalcrError {
-                        desc: "Internal error - expected AstOp to have binary branch".to_string(),
-                        span: None,
-                    })
+                if *op == And || *op == Or {
+                    return self.eval_logic_op(op, lhs, rhs).map(|num| self.number(num));
                 }
+                if *op == Xor {
+                    let (lhs, rhs) = (try!(self.eval_integer(lhs)), try!(self.eval_integer(rhs)));
+                    return Ok(self.number((lhs ^ rhs) as f64));
+                }
+                if is_chained(op, &lhs.val) {
+                    return self.eval_chain(ast);
+                }
+                let (lhs, rhs) = (try!(self.eval_number(lhs)), try!(self.eval_number(rhs)));
+                self.apply_binary(op, lhs, rhs, ast)
             },
             1 => {
                 let child = ast.get_unary_branch().unwrap();
-                let val = try!(self.eval_eq(child));
+                let val = try!(self.eval_number(child));
                 match *op {
-                    Neg => Ok(-val),
-                    Fact => self.evalf_fact(val, child),
+                    Neg => Ok(val.neg()),
+                    Fact => self.evalf_fact(val.to_f64(), child).map(|num| self.number(num)),
+                    // degrees are converted to whatever unit angles are currently in
+                    Deg => {
+                        let val = self.from_radians(val.to_f64() * f64::consts::PI / 180.0);
+                        Ok(self.number(val))
+                    },
                     _ => Err(CalcrError {
                         desc: "Internal error - expected AstOp to have unary branch".to_string(),
                         span: None,
@@ -153,20 +1525,135 @@ impl Interpreter {
         }
     }
 
+    /// Evaluates a left-associative chain such as `1+2-3+4` in a loop, so that a long sum is not
+    /// mistaken for a deeply nested expression
+    fn eval_chain(&mut self, ast: &Ast) -> CalcrResult<Number> {
+        let mut chain = vec![];
+        let mut node = ast;
+        while let Op(ref op) = node.val {
+            if node.branches.len() != 2 || !is_chained(op, &node.branches[0].val) {
+                break;
+            }
+            chain.push((op, node));
+            node = &node.branches[0];
+        }
+        let mut acc = try!(self.eval_number(node));
+        for (op, node) in chain.into_iter().rev() {
+            try!(self.check_limits(node));
+            let rhs = try!(self.eval_number(&node.branches[1]));
+            acc = try!(self.apply_binary(op, acc, rhs, node));
+        }
+        Ok(acc)
+    }
+
+    /// Applies the arithmetic or comparison operator `op` of the node `ast` to its evaluated
+    /// branches
+    fn apply_binary(&self, op: &OpKind, lhs: Number, rhs: Number, ast: &Ast)
+                    -> CalcrResult<Number> {
+        match *op {
+            Plus => Ok(lhs.add(&rhs)),
+            Minus => Ok(lhs.sub(&rhs)),
+            Mult | Of => Ok(lhs.mul(&rhs)),
+            Div => Ok(lhs.div(&rhs)),
+            Mod | IntDiv if rhs.to_f64() == 0.0 => Err(CalcrError {
+                desc: "Cannot divide by zero".to_string(),
+                span: Some(ast.branches[1].get_total_span()),
+            }),
+            Mod if self.exact => Ok(lhs.rem(&rhs)),
+            Mod => Ok(Number::Float(lhs.to_f64() % rhs.to_f64())),
+            IntDiv if self.exact => Ok(lhs.div_floor(&rhs)),
+            IntDiv => Ok(Number::Float((lhs.to_f64() / rhs.to_f64()).floor())),
+            Less => Ok(self.truth(lhs.to_f64() < rhs.to_f64())),
+            Greater => Ok(self.truth(lhs.to_f64() > rhs.to_f64())),
+            LessEq => Ok(self.truth(lhs.to_f64() <= rhs.to_f64())),
+            GreaterEq => Ok(self.truth(lhs.to_f64() >= rhs.to_f64())),
+            Equal => Ok(self.truth(lhs.to_f64() == rhs.to_f64())),
+            NotEqual => Ok(self.truth(lhs.to_f64() != rhs.to_f64())),
+            Pow if lhs.to_f64() == 0.0 && rhs.to_f64() < 0.0 => Err(CalcrError {
+                desc: "Cannot raise zero to a negative power".to_string(),
+                span: Some(ast.get_total_span()),
+            }),
+            // note that 0^0 is 1, following the usual floating point convention
+            Pow => Ok(lhs.pow(&rhs)),
+            _ => Err(CalcrError {
+                desc: "Internal error - expected AstOp to have binary branch".to_string(),
+                span: None,
+            })
+        }
+    }
+
+    /// Converts `val` to 1 if it is true, and 0 otherwise
+    fn truth(&self, val: bool) -> Number {
+        self.number(if val { 1.0 } else { 0.0 })
+    }
+
+    /// Evaluates `&&` and `||`, treating any non-zero number as true
+    ///
+    /// The right hand side is only evaluated if the left hand side does not already decide the
+    /// result, so e.g. `0 && sqrt(-1)` is simply 0.
+    fn eval_logic_op(&mut self, op: &OpKind, lhs: &Ast, rhs: &Ast) -> CalcrResult<f64> {
+        let lhs = try!(self.eval_eq(lhs)) != 0.0;
+        let out = match *op {
+            And => lhs && try!(self.eval_eq(rhs)) != 0.0,
+            Or => lhs || try!(self.eval_eq(rhs)) != 0.0,
+            _ => return Err(CalcrError {
+                desc: "Internal error - expected AstOp to be a logical operator".to_string(),
+                span: None,
+            }),
+        };
+        Ok(if out { 1.0 } else { 0.0 })
+    }
+
+    /// Evaluates `ast`, which must result in a whole number for use in bitwise operations
+    fn eval_integer(&mut self, ast: &Ast) -> CalcrResult<i64> {
+        let val = try!(self.eval_eq(ast));
+        if val.fract() == 0.0 && val.abs() <= (1u64 << 53) as f64 {
+            Ok(val as i64)
+        } else {
+            Err(CalcrError {
+                desc: "Bitwise operations only accept whole numbers".to_string(),
+                span: Some(ast.get_total_span()),
+            })
+        }
+    }
+
+    /// Returns the value of the builtin constant `c`
+    ///
+    /// Pi and e are the closest `f64`s to their exact values, as given by the standard library,
+    /// while phi is computed from its definition as the positive solution of x^2 = x + 1.
     fn eval_const(&mut self, c: &ConstKind) -> CalcrResult<f64> {
         Ok(match *c {
             Pi => f64::consts::PI,
-            E => (1.0f64).exp(),
-            Phi => 1.6180339887498948482,
+            E => f64::consts::E,
+            Phi => (1.0 + 5f64.sqrt()) / 2.0,
+            Inf => f64::INFINITY,
+            NaN => f64::NAN,
         })
     }
 
-    fn evalf_fact(&mut self, mut num: f64, child: &Ast) -> CalcrResult<f64> {
-        if num.fract() == 0.0 && num >= 0.0 {
-            let mut out = 1.0;
-            while num > 0.0 {
-                out *= num;
-                num -= 1.0;
+    fn evalf_fact(&mut self, num: f64, child: &Ast) -> CalcrResult<f64> {
+        if num > self.max_factorial as f64 {
+            Err(CalcrError {
+                desc: format!("factorial argument exceeds limit {}", self.max_factorial),
+                span: Some(child.get_total_span()),
+            })
+        } else if num.fract() == 0.0 && num >= 0.0 {
+            // Multiplying f64s accumulates rounding errors, so we compute the factorial exactly for
+            // as long as it fits in a u128 (up to 34!) and only round it to an f64 at the end.
+            let mut exact: u128 = 1;
+            let mut factor = 2.0;
+            while factor <= num {
+                match exact.checked_mul(factor as u128) {
+                    Some(prod) => exact = prod,
+                    None => break,
+                }
+                factor += 1.0;
+            }
+            let mut out = exact as f64;
+            while factor <= num && out.is_finite() {
+                try!(self.check_deadline());
+                out *= factor;
+                factor += 1.0;
             }
             Ok(out)
         } else {
@@ -176,4 +1663,1220 @@ impl Interpreter {
             })
         }
     }
-}
\ No newline at end of file
+}
+
+/// Evaluates `expr` with a new interpreter, for when no variables or settings need to be kept
+///
+/// ```
+/// assert_eq!(calcr::eval("2+2").unwrap(), Some(4.0));
+/// ```
+pub fn eval(expr: &str) -> CalcrResult<Option<f64>> {
+    Interpreter::new().eval_expression(&expr.to_string())
+}
+
+/// The default for the `copy` command, since running other programs is left to the program
+/// using the interpreter
+fn no_clipboard(_text: &str) -> CalcrResult<()> {
+    Err(CalcrError {
+        desc: "No clipboard is configured".to_string(),
+        span: None,
+    })
+}
+
+/// Computes the gamma function of `x` using the Lanczos approximation
+fn gamma(x: f64) -> f64 {
+    if x < 0.5 {
+        // the approximation only holds for x >= 0.5, so we use the reflection formula
+        f64::consts::PI / ((f64::consts::PI * x).sin() * gamma(1.0 - x))
+    } else {
+        let x = x - 1.0;
+        let t = x + LANCZOS_G + 0.5;
+        let sum = LANCZOS_COEFFS.iter()
+                                .enumerate()
+                                .skip(1)
+                                .fold(LANCZOS_COEFFS[0], |sum, (i, c)| sum + c / (x + i as f64));
+        // t^(x + 0.5) alone overflows for x above about 140, even though the result doesn't, so
+        // it is split in two halves with the small e^-t in between
+        let half_pow = t.powf((x + 0.5) / 2.0);
+        (2.0 * f64::consts::PI).sqrt() * half_pow * (-t).exp() * half_pow * sum
+    }
+}
+
+/// Computes at most `count` coefficients of the continued fraction of `num`, which must be finite
+///
+/// Exact numbers give exact coefficients, and stop once the fraction is complete. For floats the
+/// later coefficients are less accurate, as the rounding errors add up.
+fn continued_fraction(num: Number, count: usize) -> Vec<i64> {
+    let mut coeffs = Vec::new();
+    match num {
+        Number::Int(num) => coeffs.push(num),
+        Number::Rational(mut numer, mut denom) => {
+            // the Euclidean algorithm, rounding down so only the first coefficient is negative
+            while coeffs.len() < count && denom != 0 {
+                let whole = numer.div_euclid(denom);
+                coeffs.push(whole);
+                let rem = numer - whole * denom;
+                numer = denom;
+                denom = rem;
+            }
+        },
+        Number::Float(mut num) => {
+            while coeffs.len() < count {
+                let whole = num.floor();
+                coeffs.push(whole as i64);
+                let rem = num - whole;
+                // a tiny remainder would give a huge coefficient which is only rounding errors
+                if rem < 1e-9 {
+                    break;
+                }
+                num = 1.0 / rem;
+            }
+        },
+    }
+    coeffs
+}
+
+/// Writes `num` such that evaluating it gives exactly `num` again, including NaN and infinity
+fn state_number(num: f64) -> String {
+    if num.is_nan() {
+        "0/0".to_string()
+    } else if num.is_infinite() {
+        (if num > 0.0 { "1/0" } else { "-1/0" }).to_string()
+    } else {
+        // both are read back as the same number, but very large or small numbers are much
+        // shorter in scientific notation
+        let (plain, scientific) = (format!("{}", num), format!("{:e}", num));
+        if scientific.len() < plain.len() { scientific } else { plain }
+    }
+}
+
+/// Returns the nodes of `ast` which contain the variable `name`, identified by their address
+fn nodes_containing(ast: &Ast, name: &str) -> HashSet<*const Ast> {
+    // like `walk`, this avoids recursing, and puts every node before its branches
+    let mut nodes = Vec::new();
+    let mut stack = vec!(ast);
+    while let Some(ast) = stack.pop() {
+        nodes.push(ast);
+        stack.extend(ast.branches.iter());
+    }
+    // so going backwards, the branches of a node are always checked before the node itself
+    let mut containing = HashSet::new();
+    for ast in nodes.into_iter().rev() {
+        let is_name = match ast.val {
+            Name(ref var) => var == name,
+            CalledName(ref var) => var == name,
+            _ => false,
+        };
+        if is_name || ast.branches.iter().any(|br| containing.contains(&(br as *const Ast))) {
+            containing.insert(ast as *const Ast);
+        }
+    }
+    containing
+}
+
+/// The error for an equation which is not linear in the unknown, failing at `ast`
+fn nonlinear(ast: &Ast) -> CalcrError {
+    CalcrError {
+        desc: "Cannot solve nonlinear equation".to_string(),
+        span: Some(ast.get_total_span()),
+    }
+}
+
+/// Whether the binary operator `op` continues a left-associative chain ending in `lhs`, i.e.
+/// whether both are additions/subtractions or both are multiplications/divisions
+fn is_chained(op: &OpKind, lhs: &AstVal) -> bool {
+    match *lhs {
+        Op(ref lhs_op) => matches!((op, lhs_op), (&Plus | &Minus, &Plus | &Minus) |
+                                                 (&Mult | &Div, &Mult | &Div)),
+        _ => false,
+    }
+}
+
+/// Returns whether `toks` start with a binary operator, meaning they continue from `ans`
+///
+/// This is the case for `*`, `/`, `^` and the comparisons, while `+` and `-` only count if
+/// followed by whitespace, so that e.g. `- 5` is `ans - 5` but `-5` is still just negative 5.
+fn continues_last_result(toks: &[Token]) -> bool {
+    match toks.first().map(|tok| &tok.val) {
+        Some(&TokVal::Op(TokOp::Mult)) | Some(&TokVal::Op(TokOp::Div)) |
+        Some(&TokVal::Op(TokOp::Pow)) | Some(&TokVal::Op(TokOp::Xor)) => true,
+        Some(val) if is_comparison(val) => true,
+        Some(&TokVal::Op(TokOp::Plus)) | Some(&TokVal::Op(TokOp::Minus)) => {
+            toks.get(1).is_none_or(|next| next.span.0 > toks[0].span.1)
+        },
+        _ => false,
+    }
+}
+
+/// Gives general usage if `arg` is empty, and otherwise a description of the builtin `arg`
+fn eval_help_cmd(expr: &str, arg: &str) -> CalcrResult<String> {
+    if arg.is_empty() {
+        let names: Vec<_> = BUILTINS.iter().map(|builtin| builtin.names[0]).collect();
+        Ok(format!("Type an expression such as `2 + 3 * sin(pi / 4)` to evaluate it.\n\
+                    Commands: reset, undo, precision, show, explain, angle, results, copy, help\n\
+                    Builtins: {}, prevN\n\
+                    Type `help NAME` for a description of a builtin.",
+                   names.join(", ")))
+    } else if let Some(desc) = describe_builtin(arg) {
+        Ok(desc)
+    } else {
+        Err(CalcrError {
+            desc: format!("No function or constant called {}", arg),
+            span: Some(char_span(expr, arg)),
+        })
+    }
+}
+
+/// Returns the usage and description of the builtin called `name`, e.g. for `help`
+fn describe_builtin(name: &str) -> Option<String> {
+    if let Some(builtin) = find_builtin(name) {
+        Some(format!("{}: {}", builtin.usage, builtin.desc))
+    } else if let Some(PrevResult(_)) = get_builtin_name(&name.to_string()) {
+        Some("prevN: the Nth most recent result in the current batch, where prev is prev1"
+             .to_string())
+    } else {
+        None
+    }
+}
+
+/// Returns the span, in chars, of `part` within `input`
+///
+/// `part` must be a slice of `input`.
+fn char_span(input: &str, part: &str) -> (usize, usize) {
+    let byte_offset = part.as_ptr() as usize - input.as_ptr() as usize;
+    let begin = input[..byte_offset].chars().count();
+    (begin, begin + part.chars().count())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64;
+    use std::time::Duration;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use ast::{Ast, AstVal, OpKind};
+    use errors::CalcrError;
+    use lexer::LexerOptions;
+    use output::OutputFormat;
+    use super::{Answer, Interpreter, describe_builtin, eval};
+
+    #[test]
+    fn reset() {
+        let mut interp = Interpreter::new();
+        interp.eval_expression(&"x = 3".to_string()).unwrap();
+        interp.eval_expression(&"2 + 2".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"reset".to_string()), Ok(None));
+        assert_eq!(interp.eval_expression(&"ans".to_string()), Ok(Some(0.0)));
+        assert!(interp.eval_expression(&"x".to_string()).is_err());
+    }
+
+    #[test]
+    fn explain() {
+        let mut interp = Interpreter::new();
+        let expr = "explain 2+3*4";
+        assert_eq!(interp.explain(expr, &expr[8..]),
+                   Ok(vec!("3 * 4 = 12".to_string(), "2 + 12 = 14".to_string())));
+        assert_eq!(interp.explain(expr, &expr[12..]), Ok(vec!("4 = 4".to_string())));
+        let expr = "explain 1 + sqrt(-1)";
+        assert_eq!(interp.explain(expr, &expr[8..]).unwrap_err().span, Some((17, 19)));
+        // the steps are written the same way as the input
+        let mut options = LexerOptions::new();
+        options.decimal_comma = true;
+        interp.set_lexer_options(options);
+        let mut format = OutputFormat::new();
+        format.decimal_comma = true;
+        interp.set_format(format);
+        let expr = "explain hypot(1,5; 2) * 0,5";
+        assert_eq!(interp.explain(expr, &expr[8..]),
+                   Ok(vec!("hypot(1,5; 2) = 2,5".to_string(), "2,5 * 0,5 = 1,25".to_string())));
+    }
+
+    #[test]
+    fn case_sensitive_vars() {
+        let mut interp = Interpreter::new();
+        interp.eval_expression(&"X = 5".to_string()).unwrap();
+        interp.eval_expression(&"x = 2".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"X".to_string()), Ok(Some(5.0)));
+        assert_eq!(interp.eval_expression(&"x".to_string()), Ok(Some(2.0)));
+        assert_eq!(interp.eval_expression(&"Sqrt(4) + ANS".to_string()), Ok(Some(4.0)));
+    }
+
+    #[test]
+    fn degrees() {
+        let mut interp = Interpreter::new();
+        let rad = interp.eval_expression(&"90°".to_string()).unwrap().unwrap();
+        assert!((rad - f64::consts::PI / 2.0).abs() < 1e-12);
+        let sin = interp.eval_expression(&"sin(90°)".to_string()).unwrap().unwrap();
+        assert!((sin - 1.0).abs() < 1e-12);
+        let sum = interp.eval_expression(&"180° + 2".to_string()).unwrap().unwrap();
+        assert!((sum - f64::consts::PI - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn unary_precedence() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"-3!".to_string()), Ok(Some(-6.0)));
+        assert_eq!(interp.eval_expression(&"-2^2".to_string()), Ok(Some(-4.0)));
+        assert_eq!(interp.eval_expression(&"2!^2".to_string()), Ok(Some(4.0)));
+        assert!(interp.eval_expression(&"(-3)!".to_string()).is_err());
+    }
+
+    #[test]
+    fn eval_ast() {
+        let mut interp = Interpreter::new();
+        let num = |n| Ast { val: AstVal::Num(n), span: (0, 0), branches: vec!() };
+        let ast = Ast {
+            val: AstVal::Op(OpKind::Mult),
+            span: (0, 0),
+            branches: vec!(num(6.0), num(7.0)),
+        };
+        assert_eq!(interp.eval_ast(&ast), Ok(Some(Answer::Number(42.0, None))));
+        assert_eq!(interp.eval_expression(&"ans".to_string()), Ok(Some(42.0)));
+    }
+
+    #[test]
+    fn negative_powers() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"2^-3".to_string()), Ok(Some(0.125)));
+        assert_eq!(interp.eval_expression(&"0^0".to_string()), Ok(Some(1.0)));
+        let err = interp.eval_expression(&"1 + 0^-1".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((4, 8)));
+    }
+
+    #[test]
+    fn factorial_precedence() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"(2+3)!".to_string()), Ok(Some(120.0)));
+        assert_eq!(interp.eval_expression(&"2+3!".to_string()), Ok(Some(8.0)));
+        assert_eq!(interp.eval_expression(&"3!!".to_string()), Ok(Some(720.0)));
+        assert_eq!(interp.eval_expression(&"-3!".to_string()), Ok(Some(-6.0)));
+        assert_eq!(interp.eval_expression(&"2^3!".to_string()), Ok(Some(64.0)));
+    }
+
+    #[test]
+    fn word_operators() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"7 mod 3".to_string()), Ok(Some(1.0)));
+        assert_eq!(interp.eval_expression(&"-7 mod 3".to_string()), Ok(Some(-1.0)));
+        assert_eq!(interp.eval_expression(&"7.5 mod 2".to_string()), Ok(Some(1.5)));
+        assert_eq!(interp.eval_expression(&"7 div 2".to_string()), Ok(Some(3.0)));
+        assert_eq!(interp.eval_expression(&"-7 div 2".to_string()), Ok(Some(-4.0)));
+        assert_eq!(interp.eval_expression(&"2 * 7 div 2".to_string()), Ok(Some(7.0)));
+        // decimals are taken as written, rather than as the nearest binary fraction
+        assert_eq!(interp.eval_expression(&"0.3 mod 0.1".to_string()), Ok(Some(0.0)));
+        assert_eq!(interp.eval_expression(&"0.3 div 0.1".to_string()), Ok(Some(3.0)));
+        let err = interp.eval_expression(&"7 mod (1 - 1)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Cannot divide by zero");
+        assert_eq!(err.span, Some((7, 12)));
+        assert!(interp.eval_expression(&"7 div 0".to_string()).is_err());
+        assert_eq!(interp.eval_expression(&"0.2 of 50".to_string()), Ok(Some(10.0)));
+        assert_eq!(interp.eval_expression(&"20/100 of 50".to_string()), Ok(Some(10.0)));
+        assert_eq!(interp.eval_expression(&"1/2 of 10 + 1".to_string()), Ok(Some(6.0)));
+    }
+
+    #[test]
+    fn constants() {
+        let mut interp = Interpreter::new();
+        let phi = interp.eval_expression(&"phi".to_string()).unwrap().unwrap();
+        assert!((phi * phi - (phi + 1.0)).abs() < 1e-15);
+        assert_eq!(phi, 1.6180339887498948482);
+        assert_eq!(interp.eval_expression(&"e".to_string()), Ok(Some(f64::consts::E)));
+        assert_eq!(interp.eval_expression(&"π".to_string()), Ok(Some(f64::consts::PI)));
+    }
+
+    #[test]
+    fn infinity_and_nan() {
+        let mut interp = Interpreter::new();
+        let res = interp.eval_expression(&"atan(inf)".to_string()).unwrap().unwrap();
+        assert!((res - f64::consts::PI / 2.0).abs() < 1e-12);
+        assert_eq!(interp.eval_expression(&"-inf".to_string()), Ok(Some(f64::NEG_INFINITY)));
+        assert_eq!(interp.eval_expression(&"1 / inf".to_string()), Ok(Some(0.0)));
+        assert!(interp.eval_expression(&"nan + 1".to_string()).unwrap().unwrap().is_nan());
+        // only the result is checked in the strict domain, not the inputs
+        interp.set_strict_domain(true);
+        let res = interp.eval_expression(&"atan(-inf)".to_string()).unwrap().unwrap();
+        assert!((res + f64::consts::PI / 2.0).abs() < 1e-12);
+        assert!(interp.eval_expression(&"inf".to_string()).is_err());
+    }
+
+    #[test]
+    fn unary_plus() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"3 + +5".to_string()), Ok(Some(8.0)));
+        assert_eq!(interp.eval_expression(&"3 - +5".to_string()), Ok(Some(-2.0)));
+        assert_eq!(interp.eval_expression(&"-+5".to_string()), Ok(Some(-5.0)));
+        assert_eq!(interp.eval_expression(&"3 * - +5".to_string()), Ok(Some(-15.0)));
+        assert_eq!(interp.eval_expression(&"2^+2".to_string()), Ok(Some(4.0)));
+    }
+
+    #[test]
+    fn exponent_signs() {
+        let mut interp = Interpreter::new();
+        let inv_root = 2f64.powf(-0.5);
+        assert_eq!(interp.eval_expression(&"2^(-0.5)".to_string()), Ok(Some(inv_root)));
+        assert_eq!(interp.eval_expression(&"2^-0.5".to_string()), Ok(Some(inv_root)));
+        assert_eq!(interp.eval_expression(&"2^(1/2)".to_string()), Ok(Some(2f64.sqrt())));
+        assert_eq!(interp.eval_expression(&"-2^2".to_string()), Ok(Some(-4.0)));
+        assert_eq!(interp.eval_expression(&"2^-2^2".to_string()), Ok(Some(0.0625)));
+    }
+
+    #[test]
+    fn named_results() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"#area = 3*4".to_string()), Ok(Some(12.0)));
+        assert_eq!(interp.eval_expression(&"#base=2".to_string()), Ok(Some(2.0)));
+        assert_eq!(interp.eval_expression(&"area / 2".to_string()), Ok(Some(6.0)));
+        assert_eq!(interp.list_results(), vec!("#area = 12", "#base = 2"));
+        assert!(interp.eval_expression(&"#pi = 3".to_string()).is_err());
+        let err = interp.eval_expression(&"#x = 1 +".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((8, 8)));
+        // the name is checked like that of any variable
+        assert_eq!(interp.eval_expression(&"#side_a = 3".to_string()), Ok(Some(3.0)));
+        for &expr in ["#_ = 1", "#mod = 1", "#sin = 1", "#a, b = 1, 2", "#2 = 1"].iter() {
+            assert!(interp.eval_expression(&expr.to_string()).is_err());
+        }
+    }
+
+    #[test]
+    fn undo_named_result() {
+        let mut interp = Interpreter::new();
+        interp.eval_expression(&"1".to_string()).unwrap();
+        interp.eval_expression(&"#area = 3*4".to_string()).unwrap();
+        interp.eval_expression(&"undo".to_string()).unwrap();
+        assert!(interp.eval_expression(&"area".to_string()).is_err());
+        assert_eq!(interp.eval_expression(&"ans".to_string()), Ok(Some(1.0)));
+        assert!(interp.list_results().is_empty());
+        // an imaginary result is rejected without changing `ans`
+        interp.set_imaginary_results(true);
+        assert!(interp.eval_expression(&"#r = sqrt(-4)".to_string()).is_err());
+        assert_eq!(interp.eval_expression(&"ans".to_string()), Ok(Some(1.0)));
+    }
+
+    #[test]
+    fn exp_overflow() {
+        let mut interp = Interpreter::new();
+        let err = interp.eval_expression(&"exp(1000)".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((4, 8)));
+        assert_eq!(interp.eval_expression(&"exp(-1000)".to_string()), Ok(Some(0.0)));
+    }
+
+    #[test]
+    fn angle_mode() {
+        let mut interp = Interpreter::new();
+        interp.eval_expression(&"angle deg".to_string()).unwrap();
+        let sin = interp.eval_expression(&"sin(90)".to_string()).unwrap().unwrap();
+        assert!((sin - 1.0).abs() < 1e-12);
+        let sin = interp.eval_expression(&"sin(30°)".to_string()).unwrap().unwrap();
+        assert!((sin - 0.5).abs() < 1e-12);
+        let acos = interp.eval_expression(&"acos(0)".to_string()).unwrap().unwrap();
+        assert!((acos - 90.0).abs() < 1e-12);
+        assert!(interp.eval_expression(&"angle grad".to_string()).is_err());
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!(describe_builtin("sin"), Some("sin(x): sine of the angle x".to_string()));
+        assert_eq!(describe_builtin("π"), describe_builtin("pi"));
+        assert!(describe_builtin("prev2").is_some());
+        assert_eq!(describe_builtin("foo"), None);
+        let mut interp = Interpreter::new();
+        let err = interp.eval_expression(&"help foo".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((5, 8)));
+        assert_eq!(interp.eval_expression(&"help sin".to_string()), Ok(None));
+        assert_eq!(interp.command_output(), Some("sin(x): sine of the angle x"));
+    }
+
+    #[test]
+    fn command_output() {
+        let mut interp = Interpreter::new();
+        let mut output = |expr: &str| {
+            assert_eq!(interp.eval_expression(&expr.to_string()), Ok(None));
+            interp.command_output().map(|text| text.to_string())
+        };
+        assert_eq!(output("precision"), Some("Precision: full".to_string()));
+        assert_eq!(output("precision 3"), None);
+        assert_eq!(output("precision"), Some("Precision: 3 decimal places".to_string()));
+        assert_eq!(output("show fraction"), None);
+        assert_eq!(output("show"), Some("Showing results as fraction".to_string()));
+        assert_eq!(output("angle deg"), None);
+        assert_eq!(output("angle"), Some("Angles are in degrees".to_string()));
+        assert_eq!(output("explain 2+3*4"), Some("3 * 4 = 12\n2 + 12 = 14".to_string()));
+        assert_eq!(output("results"), Some("No results have been named yet".to_string()));
+        assert!(output("help").unwrap().starts_with("Type an expression"));
+        // anything else clears the output of the previous command
+        assert_eq!(output("x = 2"), None);
+    }
+
+    #[test]
+    fn command_names_as_variables() {
+        let mut interp = Interpreter::new();
+        // every command word can still be assigned to and used in expressions
+        for name in ["reset", "undo", "precision", "show", "explain", "angle", "results", "copy",
+                     "help"].iter() {
+            assert_eq!(interp.eval_expression(&format!("{} = 3", name)), Ok(None));
+            assert_eq!(interp.eval_expression(&format!("{} * 2", name)), Ok(Some(6.0)));
+        }
+        assert_eq!(interp.eval_expression(&"explain 2 * 3".to_string()), Ok(None));
+        assert_eq!(interp.command_output(), Some("2 * 3 = 6"));
+    }
+
+    #[test]
+    fn imaginary_sqrt() {
+        let mut interp = Interpreter::new();
+        // without being shown as imaginary, the magnitude alone would be the wrong answer
+        let err = interp.eval_expression(&"sqrt(-4)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Complex arithmetic is not supported");
+        assert_eq!(err.span, Some((5, 7)));
+        assert!(eval("sqrt(-4)").is_err());
+        interp.set_imaginary_results(true);
+        let res = interp.eval_answer(&"sqrt(-4)".to_string()).unwrap().unwrap();
+        assert_eq!(res, Answer::Imaginary(2.0));
+        assert_eq!(interp.format_answer(&res), "2i");
+        assert!(interp.eval_expression(&"ans + 1".to_string()).is_err());
+        assert!(interp.eval_expression(&"sqrt(-4) + 1".to_string()).is_err());
+        assert!(interp.eval_expression(&"x = sqrt(-4)".to_string()).is_err());
+        let res = interp.eval_answer(&"sqrt(9)".to_string()).unwrap().unwrap();
+        assert_eq!(interp.format_answer(&res), "3");
+        interp.eval_expression(&"undo".to_string()).unwrap();
+        assert!(interp.eval_expression(&"ans + 1".to_string()).is_err());
+        let res = interp.eval_answer(&"? sqrt(-9)".to_string()).unwrap().unwrap();
+        assert_eq!(interp.format_answer(&res), "3i");
+    }
+
+    #[test]
+    fn register_fn() {
+        let mut interp = Interpreter::new();
+        interp.register_fn("ctof", 1, |args| Ok(args[0] * 9.0 / 5.0 + 32.0)).unwrap();
+        interp.register_fn("norm", 2, |args| Ok(args[0].hypot(args[1]))).unwrap();
+        assert_eq!(interp.eval_expression(&"ctof(100)".to_string()), Ok(Some(212.0)));
+        assert_eq!(interp.eval_expression(&"2 norm(3, 4)".to_string()), Ok(Some(10.0)));
+        let err = interp.eval_expression(&"norm(3)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Function takes exactly 2 argument(s)");
+    }
+
+    #[test]
+    fn register_builtin_name() {
+        let mut interp = Interpreter::new();
+        for name in ["hypot", "Sin", "pi", "mod", "log_2"].iter() {
+            let err = interp.register_fn(name, 1, |args| Ok(args[0])).unwrap_err();
+            assert_eq!(err.desc, format!("Cannot register a function named {}, which is builtin",
+                                         name));
+        }
+        assert_eq!(interp.eval_expression(&"hypot(3, 4)".to_string()), Ok(Some(5.0)));
+    }
+
+    #[test]
+    fn caret_modes() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"2^3".to_string()), Ok(Some(8.0)));
+        assert_eq!(interp.eval_expression(&"2**3".to_string()), Ok(Some(8.0)));
+        let mut options = LexerOptions::new();
+        options.caret_xor = true;
+        interp.set_lexer_options(options);
+        assert_eq!(interp.eval_expression(&"2^3".to_string()), Ok(Some(1.0)));
+        assert_eq!(interp.eval_expression(&"2**3".to_string()), Ok(Some(8.0)));
+        assert_eq!(interp.eval_expression(&"1 + 2^3 + 4".to_string()), Ok(Some(4.0)));
+        let err = interp.eval_expression(&"2.5^1".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((0, 3)));
+        // explanations are written the way they would be typed in this mode
+        interp.eval_expression(&"explain 2^3**2".to_string()).unwrap();
+        assert_eq!(interp.command_output(), Some("3 ** 2 = 9\n2 ^ 9 = 11"));
+    }
+
+    #[test]
+    fn query_without_ans() {
+        let mut interp = Interpreter::new();
+        interp.eval_expression(&"5".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"? 2+2".to_string()), Ok(Some(4.0)));
+        assert_eq!(interp.eval_expression(&"?ans * 2; prev + 1".to_string()), Ok(Some(11.0)));
+        assert_eq!(interp.eval_expression(&"ans".to_string()), Ok(Some(5.0)));
+        let err = interp.eval_expression(&" ? 1 +".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((6, 6)));
+    }
+
+    #[test]
+    fn frac_and_int() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"frac(3.75)".to_string()), Ok(Some(0.75)));
+        assert_eq!(interp.eval_expression(&"int(3.75)".to_string()), Ok(Some(3.0)));
+        assert_eq!(interp.eval_expression(&"frac(-3.75)".to_string()), Ok(Some(-0.75)));
+        assert_eq!(interp.eval_expression(&"int(-3.75)".to_string()), Ok(Some(-3.0)));
+    }
+
+    #[test]
+    fn gamma() {
+        let mut interp = Interpreter::new();
+        let res = interp.eval_expression(&"gamma(5)".to_string()).unwrap().unwrap();
+        assert!((res - 24.0).abs() < 1e-10);
+        let res = interp.eval_expression(&"gamma(0.5)".to_string()).unwrap().unwrap();
+        assert!((res - f64::consts::PI.sqrt()).abs() < 1e-10);
+        let res = interp.eval_expression(&"gamma(-0.5)".to_string()).unwrap().unwrap();
+        assert!((res + 2.0 * f64::consts::PI.sqrt()).abs() < 1e-10);
+        let err = interp.eval_expression(&"gamma(1 - 3)".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((6, 11)));
+    }
+
+    #[test]
+    fn gamma_large() {
+        let mut interp = Interpreter::new();
+        // i.e. 149! and 170!, the largest factorial an f64 can hold
+        let res = interp.eval_expression(&"gamma(150)".to_string()).unwrap().unwrap();
+        assert!((res / 3.80892263763057e260 - 1.0).abs() < 1e-10);
+        let res = interp.eval_expression(&"gamma(171)".to_string()).unwrap().unwrap();
+        assert!((res / 7.257415615307999e306 - 1.0).abs() < 1e-10);
+        let res = interp.eval_expression(&"gamma(172)".to_string()).unwrap().unwrap();
+        assert!(res.is_infinite());
+    }
+
+    #[test]
+    fn reciprocal_trig() {
+        let mut interp = Interpreter::new();
+        let res = interp.eval_expression(&"sec(pi/3)".to_string()).unwrap().unwrap();
+        assert!((res - 2.0).abs() < 1e-10);
+        let res = interp.eval_expression(&"csc(pi/6)".to_string()).unwrap().unwrap();
+        assert!((res - 2.0).abs() < 1e-10);
+        let res = interp.eval_expression(&"cot(pi/4)".to_string()).unwrap().unwrap();
+        assert!((res - 1.0).abs() < 1e-10);
+        let err = interp.eval_expression(&"csc(1 - 1)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "csc is undefined where sin is zero");
+        assert_eq!(err.span, Some((4, 9)));
+        assert!(interp.eval_expression(&"cot(0)".to_string()).is_err());
+        // the rounding of pi must not turn these into huge numbers
+        let err = interp.eval_expression(&"sec(pi/2)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "sec is undefined where cos is zero");
+        let err = interp.eval_expression(&"csc(pi)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "csc is undefined where sin is zero");
+        assert_eq!(err.span, Some((4, 6)));
+        interp.eval_expression(&"angle deg".to_string()).unwrap();
+        let res = interp.eval_expression(&"sec(60)".to_string()).unwrap().unwrap();
+        assert!((res - 2.0).abs() < 1e-10);
+        assert!(interp.eval_expression(&"sec(90)".to_string()).is_err());
+    }
+
+    #[test]
+    fn hypot() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"hypot(3, 4)".to_string()), Ok(Some(5.0)));
+        let res = interp.eval_expression(&"hypot(3e200, 4e200)".to_string()).unwrap().unwrap();
+        assert!((res / 5e200 - 1.0).abs() < 1e-12);
+        assert_eq!(interp.eval_expression(&"sqrt((3e200)^2 + (4e200)^2)".to_string()),
+                   Ok(Some(f64::INFINITY)));
+        assert!(interp.eval_expression(&"hypot(3)".to_string()).is_err());
+    }
+
+    #[test]
+    fn dist() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"dist(7, 3)".to_string()), Ok(Some(4.0)));
+        assert_eq!(interp.eval_expression(&"dist(3, 7)".to_string()), Ok(Some(4.0)));
+        assert_eq!(interp.eval_expression(&"dist(-2, 3)".to_string()), Ok(Some(5.0)));
+    }
+
+    #[test]
+    fn floor_and_ceil() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"floor(-3.5)".to_string()), Ok(Some(-4.0)));
+        assert_eq!(interp.eval_expression(&"ceil(-3.5)".to_string()), Ok(Some(-3.0)));
+        assert_eq!(interp.eval_expression(&"⌊3.7⌋".to_string()), Ok(Some(3.0)));
+        assert_eq!(interp.eval_expression(&"2⌈3.2⌉".to_string()), Ok(Some(8.0)));
+    }
+
+    #[test]
+    fn copy_cmd() {
+        let mut interp = Interpreter::new();
+        let err = interp.eval_expression(&"copy 1".to_string()).unwrap_err();
+        assert_eq!(err.desc, "No clipboard is configured");
+        let copied = Rc::new(RefCell::new(String::new()));
+        let clipboard = copied.clone();
+        interp.set_clipboard(move |text| {
+            *clipboard.borrow_mut() = text.to_string();
+            Ok(())
+        });
+        assert_eq!(interp.eval_expression(&"copy 1/4 + 2".to_string()), Ok(Some(2.25)));
+        // the result is copied as it is shown
+        assert_eq!(*copied.borrow(), "9/4");
+        // the result is stored in ans like any other
+        assert_eq!(interp.eval_expression(&"ans * 2".to_string()), Ok(Some(4.5)));
+        let err = interp.eval_expression(&"copy 1 +".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((8, 8)));
+        interp.set_clipboard(|_| Err(CalcrError {
+            desc: "No clipboard utility was found, tried xclip".to_string(),
+            span: None,
+        }));
+        assert!(interp.eval_expression(&"copy 3".to_string()).is_err());
+    }
+
+    #[test]
+    fn snap() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"snap(7, 5)".to_string()), Ok(Some(5.0)));
+        assert_eq!(interp.eval_expression(&"snap(8, 5)".to_string()), Ok(Some(10.0)));
+        assert_eq!(interp.eval_expression(&"snap(-8, 5)".to_string()), Ok(Some(-10.0)));
+        assert_eq!(interp.eval_expression(&"snap(-7, -5)".to_string()), Ok(Some(-5.0)));
+        let res = interp.eval_answer(&"snap(0.27, 0.1)".to_string()).unwrap().unwrap();
+        assert_eq!(interp.format_answer(&res), "0.3");
+        let err = interp.eval_expression(&"snap(3, 1 - 1)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "The step to snap to cannot be zero");
+        assert_eq!(err.span, Some((8, 13)));
+    }
+
+    #[test]
+    fn log_base() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"log_2(8)".to_string()), Ok(Some(3.0)));
+        assert_eq!(interp.eval_expression(&"log_10(100)".to_string()), Ok(Some(2.0)));
+        assert_eq!(interp.eval_expression(&"log_10 1000".to_string()), Ok(Some(3.0)));
+        assert_eq!(interp.eval_expression(&"log(8, 2)".to_string()), Ok(Some(3.0)));
+        assert_eq!(interp.eval_expression(&"LOG_3(81)".to_string()), Ok(Some(4.0)));
+        let err = interp.eval_expression(&"log(8, 2, 3)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Function takes one or two arguments");
+        let err = interp.eval_expression(&"log_2(8, 3)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Function takes exactly one argument");
+        assert_eq!(err.span, Some((0, 10)));
+        let err = interp.eval_expression(&"log_0(5)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "The base of a logarithm must be positive and not 1");
+        assert_eq!(err.span, Some((4, 5)));
+        let err = interp.eval_expression(&"log_1(5)".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((4, 5)));
+        let err = interp.eval_expression(&"log_2(0)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Cannot take the logarithm of a non-positive number");
+        assert_eq!(err.span, Some((6, 7)));
+    }
+
+    #[test]
+    fn last_result_var() {
+        let mut interp = Interpreter::new();
+        assert!(interp.eval_expression(&"_".to_string()).is_err());
+        interp.eval_expression(&"3+4".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"_ + 1".to_string()), Ok(Some(8.0)));
+        assert_eq!(interp.eval_expression(&"2_".to_string()), Ok(Some(16.0)));
+        let err = interp.eval_expression(&"_ = 2".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((0, 1)));
+        assert_eq!(interp.eval_expression(&"? _".to_string()), Ok(Some(16.0)));
+        interp.eval_expression(&"undo".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"? _".to_string()), Ok(Some(8.0)));
+        // setting ans directly, e.g. from CALCR_ANS, also sets `_`
+        let mut interp = Interpreter::new();
+        interp.set_last_result(5.0);
+        assert_eq!(interp.eval_expression(&"_ * 2".to_string()), Ok(Some(10.0)));
+    }
+
+    #[test]
+    fn check_expression() {
+        let interp = Interpreter::new();
+        assert_eq!(interp.check_expression(&"sqrt(-1) + x; * 2".to_string()), Ok(()));
+        let err = interp.check_expression(&"1 + 2; (3 * 4".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((7, 8)));
+    }
+
+    #[test]
+    fn to_base() {
+        let mut interp = Interpreter::new();
+        for &(expr, shown) in [("tobase(10, 2)", "0b1010"),
+                               ("tobase(15, 8)", "0o17"),
+                               ("tobase(255, 16)", "0xff")].iter() {
+            let res = interp.eval_answer(&expr.to_string()).unwrap().unwrap();
+            assert_eq!(interp.format_answer(&res), shown);
+        }
+        let res = interp.eval_answer(&"ans + 1".to_string()).unwrap().unwrap();
+        assert_eq!(res, Answer::Number(256.0, None));
+        assert_eq!(interp.format_answer(&res), "256");
+        let err = interp.eval_expression(&"tobase(2.5, 2)".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((7, 10)));
+        assert!(interp.eval_expression(&"tobase(2, 1)".to_string()).is_err());
+        assert!(interp.eval_expression(&"1 + tobase(2, 2)".to_string()).is_err());
+    }
+
+    #[test]
+    fn exact_fractions() {
+        let mut interp = Interpreter::new();
+        let res = interp.eval_answer(&"1/3 + 1/3".to_string()).unwrap().unwrap();
+        assert_eq!(res, Answer::Number(2.0 / 3.0, Some((2, 3))));
+        assert_eq!(interp.format_answer(&res), "2/3");
+        let res = interp.eval_answer(&"2/3 * 3/4".to_string()).unwrap().unwrap();
+        assert_eq!(interp.format_answer(&res), "1/2");
+        let res = interp.eval_answer(&"(1/3) * 3".to_string()).unwrap().unwrap();
+        assert_eq!(interp.format_answer(&res), "1");
+        let res = interp.eval_answer(&"-(2/3)^2".to_string()).unwrap().unwrap();
+        assert_eq!(interp.format_answer(&res), "-4/9");
+        // anything inexact falls back to floating point
+        let res = interp.eval_answer(&"sqrt(2)/3".to_string()).unwrap().unwrap();
+        assert_eq!(interp.format_answer(&res), interp.format_result(2f64.sqrt() / 3.0));
+        interp.set_exact(false);
+        let res = interp.eval_answer(&"1/3 + 1/3".to_string()).unwrap().unwrap();
+        assert_eq!(interp.format_answer(&res), interp.format_result(1.0 / 3.0 + 1.0 / 3.0));
+    }
+
+    #[test]
+    fn comparisons() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"3 < 5".to_string()), Ok(Some(1.0)));
+        assert_eq!(interp.eval_expression(&"3 >= 5".to_string()), Ok(Some(0.0)));
+        assert_eq!(interp.eval_expression(&"1/3 * 3 == 1".to_string()), Ok(Some(1.0)));
+        assert_eq!(interp.eval_expression(&"2 != 2 || 1 <= 1".to_string()), Ok(Some(1.0)));
+        interp.eval_expression(&"4".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"> 3".to_string()), Ok(Some(1.0)));
+    }
+
+    #[test]
+    fn bool_words() {
+        let mut interp = Interpreter::new();
+        let mut format = OutputFormat::new();
+        format.bool_words = true;
+        interp.set_format(format);
+        let res = interp.eval_answer(&"3 < 5".to_string()).unwrap().unwrap();
+        assert_eq!(interp.format_answer(&res), "true");
+        let res = interp.eval_answer(&"3 > 5 && 1".to_string()).unwrap().unwrap();
+        assert_eq!(interp.format_answer(&res), "false");
+        let res = interp.eval_answer(&"(3 < 5) + 1".to_string()).unwrap().unwrap();
+        assert_eq!(interp.format_answer(&res), "2");
+    }
+
+    #[test]
+    fn strict_domain() {
+        let mut interp = Interpreter::new();
+        assert!(interp.eval_expression(&"0/0".to_string()).unwrap().unwrap().is_nan());
+        interp.set_strict_domain(true);
+        interp.eval_expression(&"2".to_string()).unwrap();
+        let err = interp.eval_expression(&"1 + 0/0".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Result is not a number");
+        assert_eq!(err.span, Some((0, 7)));
+        let err = interp.eval_expression(&"(1/0)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Result is infinite");
+        assert_eq!(err.span, Some((0, 5)));
+        assert_eq!(interp.eval_expression(&"ans".to_string()), Ok(Some(2.0)));
+        // the more precise errors of single functions are still given first
+        let err = interp.eval_expression(&"1 + gamma(0)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "The gamma function is undefined for non-positive whole numbers");
+    }
+
+    #[test]
+    fn solve() {
+        let mut interp = Interpreter::new();
+        for &(expr, shown) in [("solve(2*x + 3 = 7, x)", "x = 2"),
+                               ("solve(x/3 = 1 - x, x)", "x = 3/4"),
+                               ("solve(5 = 2(y - 1) + y, y)", "y = 7/3"),
+                               ("solve(-x == 4, x)", "x = -4")].iter() {
+            let res = interp.eval_answer(&expr.to_string()).unwrap().unwrap();
+            assert_eq!(interp.format_answer(&res), shown);
+        }
+        // the unknown is not affected by a variable of the same name
+        interp.eval_expression(&"x = 10".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"1 + solve(x - 1 = 0, x)".to_string()),
+                   Ok(Some(2.0)));
+        let err = interp.eval_expression(&"solve(x^2 = 4, x)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Cannot solve nonlinear equation");
+        assert_eq!(err.span, Some((6, 9)));
+        let err = interp.eval_expression(&"solve(x * (x + 1) = 0, x)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Cannot solve nonlinear equation");
+        let err = interp.eval_expression(&"solve(x + 1 = x, x)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "The equation has no solution");
+        assert!(interp.eval_expression(&"solve(x + 1, x)".to_string()).is_err());
+        assert!(interp.eval_expression(&"solve(x = 1, 2)".to_string()).is_err());
+    }
+
+    #[test]
+    fn solve_limits() {
+        let mut interp = Interpreter::new();
+        // a long sum is looped over rather than recursed into, so it does not overflow the stack
+        let sum = vec!("x"; 20000).join(" + ");
+        let res = interp.eval_expression(&format!("solve({} = 1, x)", sum));
+        assert_eq!(res, Ok(Some(0.00005)));
+        let err = interp.eval_expression(&format!("solve({} * x = 1, x)", sum)).unwrap_err();
+        assert_eq!(err.desc, "Cannot solve nonlinear equation");
+        let sum = vec!("x"; 100).join(" + ");
+        let res = interp.eval_expression(&format!("solve({} = 50, x)", sum));
+        assert_eq!(res, Ok(Some(0.5)));
+    }
+
+    #[test]
+    fn timeout() {
+        let mut interp = Interpreter::new();
+        interp.eval_expression(&"x = 1".to_string()).unwrap();
+        // a deadline which has already passed fails at the first step
+        let expr = "x = 2 + 3".to_string();
+        let err = interp.eval_with_timeout(&expr, Duration::from_secs(0)).unwrap_err();
+        assert_eq!(err.desc, "Evaluation timed out");
+        assert_eq!(interp.eval_expression(&"x".to_string()), Ok(Some(1.0)));
+        let timeout = Duration::from_secs(60);
+        assert_eq!(interp.eval_with_timeout(&"x + 1".to_string(), timeout), Ok(Some(2.0)));
+        // a timeout too long to represent is no limit at all
+        let timeout = Duration::new(u64::MAX, 0);
+        assert_eq!(interp.eval_with_timeout(&"x + 1".to_string(), timeout), Ok(Some(2.0)));
+        // the timeout only applies to that one evaluation
+        assert_eq!(interp.eval_expression(&expr), Ok(None));
+        assert_eq!(interp.eval_expression(&"x".to_string()), Ok(Some(5.0)));
+    }
+
+    #[test]
+    fn save_and_load_state() {
+        let mut interp = Interpreter::new();
+        for expr in ["x = 1/3", "y = -2.5e-200", "big = 2^70", "undefined = 0/0", "7"].iter() {
+            interp.eval_expression(&expr.to_string()).unwrap();
+        }
+        let state = interp.save_state();
+        assert!(state.starts_with("big = 1.1805916207174113e21\nundefined = 0/0\nx = "));
+        assert!(state.contains("\ny = -2.5e-200\n"));
+        assert!(state.ends_with("\n7\n"));
+
+        let mut restored = Interpreter::new();
+        let mut options = LexerOptions::new();
+        options.decimal_comma = true;
+        restored.set_lexer_options(options);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.vars.len(), interp.vars.len());
+        for (name, val) in interp.vars.iter() {
+            let restored_val = restored.vars[name];
+            assert!(restored_val == *val || (restored_val.is_nan() && val.is_nan()));
+        }
+        assert_eq!(restored.last_result, 7.0);
+        assert!(restored.load_state("x = ").is_err());
+    }
+
+    #[test]
+    fn load_state_strict_domain() {
+        let mut interp = Interpreter::new();
+        for expr in ["x = 0/0", "y = -1/0", "1/0"].iter() {
+            interp.eval_expression(&expr.to_string()).unwrap();
+        }
+        let state = interp.save_state();
+        let mut restored = Interpreter::new();
+        restored.set_strict_domain(true);
+        restored.load_state(&state).unwrap();
+        assert!(restored.vars["x"].is_nan());
+        assert_eq!(restored.vars["y"], f64::NEG_INFINITY);
+        assert_eq!(restored.last_result, f64::INFINITY);
+        // the setting still applies afterwards
+        let err = restored.eval_expression(&"x".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Result is not a number");
+    }
+
+    #[test]
+    fn eval_over() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_over("x^2", "x", 0.0, 2.0, 3),
+                   Ok(vec!((0.0, 0.0), (1.0, 1.0), (2.0, 4.0))));
+        assert_eq!(interp.eval_over("x + 1", "x", 5.0, 9.0, 1), Ok(vec!((5.0, 6.0))));
+        assert_eq!(interp.eval_over("x", "x", 0.0, 1.0, 0), Ok(vec!()));
+        // the variable is restored afterwards, and ans is left alone
+        interp.eval_expression(&"x = 7".to_string()).unwrap();
+        interp.eval_over("2x", "x", -1.0, 1.0, 5).unwrap();
+        assert_eq!(interp.eval_expression(&"x".to_string()), Ok(Some(7.0)));
+        interp.eval_over("2y", "y", -1.0, 1.0, 5).unwrap();
+        assert!(interp.eval_expression(&"y".to_string()).is_err());
+        let err = interp.eval_over("1/sqrt(x)", "x", -1.0, 1.0, 3).unwrap_err();
+        assert_eq!(err.span, Some((7, 8)));
+    }
+
+    #[test]
+    fn cont_frac() {
+        let mut interp = Interpreter::new();
+        for &(expr, shown) in [("cf(pi, 2)", "[3; 7]"),
+                               ("cf(pi, 5)", "[3; 7, 15, 1, 292]"),
+                               ("cf(-7/3, 10)", "[-3; 1, 2]"),
+                               ("cf(4, 3)", "[4]"),
+                               ("cf(0.5, 3)", "[0; 2]")].iter() {
+            let res = interp.eval_answer(&expr.to_string()).unwrap().unwrap();
+            assert_eq!(interp.format_answer(&res), shown);
+        }
+        // the result is the value of the continued fraction, here 3 + 1/(7 + 1/(15 + 1/1))
+        let res = interp.eval_expression(&"cf(pi, 4)".to_string()).unwrap().unwrap();
+        assert_eq!(res, 355.0 / 113.0);
+        let err = interp.eval_expression(&"cf(pi, 0)".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((7, 8)));
+        assert!(interp.eval_expression(&"1 + cf(pi, 2)".to_string()).is_err());
+    }
+
+    #[test]
+    fn undefined_names() {
+        let mut interp = Interpreter::new();
+        let err = interp.eval_expression(&"y = x + 1".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Undefined variable: x");
+        assert_eq!(err.span, Some((4, 5)));
+        let err = interp.eval_expression(&"2 + foo(3)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Unknown function: foo");
+        assert_eq!(err.span, Some((4, 7)));
+        // with a space it is a multiplication rather than a call
+        let err = interp.eval_expression(&"foo (3)".to_string()).unwrap_err();
+        assert_eq!(err.desc, "Undefined variable: foo");
+        interp.eval_expression(&"x = 2".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"x(3)".to_string()), Ok(Some(6.0)));
+        // which is also how the unknown of an equation is treated
+        assert_eq!(interp.eval_expression(&"solve(x(3) = 1 + x, x)".to_string()), Ok(Some(0.5)));
+    }
+
+    #[test]
+    fn physics_constants() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"c".to_string()).unwrap_err().desc,
+                   "Undefined variable: c");
+        interp.set_physics(true);
+        assert_eq!(interp.eval_expression(&"c".to_string()), Ok(Some(299792458.0)));
+        assert_eq!(interp.eval_expression(&"2 N_A".to_string()), Ok(Some(2.0 * 6.02214076e23)));
+        // a number directly followed by h is not hexadecimal
+        let planck = interp.eval_expression(&"2*h".to_string()).unwrap();
+        assert_eq!(planck, Some(2.0 * 6.62607015e-34));
+        assert_eq!(interp.eval_expression(&"2h".to_string()), Ok(planck));
+        let err = interp.eval_expression(&"c = 3".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((0, 1)));
+    }
+
+    #[test]
+    fn continue_from_ans() {
+        let mut interp = Interpreter::new();
+        interp.eval_expression(&"10".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"* 2".to_string()), Ok(Some(20.0)));
+        assert_eq!(interp.eval_expression(&"/4".to_string()), Ok(Some(5.0)));
+        assert_eq!(interp.eval_expression(&"+ 1".to_string()), Ok(Some(6.0)));
+        assert_eq!(interp.eval_expression(&"- 2".to_string()), Ok(Some(4.0)));
+        assert_eq!(interp.eval_expression(&"^2".to_string()), Ok(Some(16.0)));
+        assert_eq!(interp.eval_expression(&"-5".to_string()), Ok(Some(-5.0)));
+        assert_eq!(interp.eval_expression(&"-(2)".to_string()), Ok(Some(-2.0)));
+        assert_eq!(interp.eval_expression(&"3; * 2".to_string()), Ok(Some(6.0)));
+        let err = interp.eval_expression(&"* 2 +".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((5, 5)));
+    }
+
+    #[test]
+    fn continuation_whitespace() {
+        let mut interp = Interpreter::new();
+        interp.eval_expression(&"10".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"- 5".to_string()), Ok(Some(5.0)));
+        assert_eq!(interp.eval_expression(&"-\t5".to_string()), Ok(Some(0.0)));
+        assert_eq!(interp.eval_expression(&"-5".to_string()), Ok(Some(-5.0)));
+        assert_eq!(interp.eval_expression(&"* 5".to_string()), Ok(Some(-25.0)));
+        assert_eq!(interp.eval_expression(&"*5".to_string()), Ok(Some(-125.0)));
+        // without the space it is a unary plus rather than `ans + 5`
+        assert_eq!(interp.eval_expression(&"+5".to_string()), Ok(Some(5.0)));
+        assert_eq!(interp.eval_expression(&"+ 5".to_string()), Ok(Some(10.0)));
+    }
+
+    #[test]
+    fn max_factorial() {
+        let mut interp = Interpreter::new();
+        let err = interp.eval_expression(&"1000000!".to_string()).unwrap_err();
+        assert_eq!(err.desc, "factorial argument exceeds limit 10000");
+        assert_eq!(err.span, Some((0, 7)));
+        interp.set_max_factorial(5);
+        assert_eq!(interp.eval_expression(&"5!".to_string()), Ok(Some(120.0)));
+        assert!(interp.eval_expression(&"6!".to_string()).is_err());
+    }
+
+    #[test]
+    fn eval_each() {
+        let mut interp = Interpreter::new();
+        let mut seen = Vec::new();
+        interp.eval_each(vec!("2 + 3", "ans * 2", "1 +"), |expr, result| {
+            seen.push((expr.to_string(), result.ok()));
+        });
+        assert_eq!(seen, vec!(("2 + 3".to_string(), Some(Some(5.0))),
+                              ("ans * 2".to_string(), Some(Some(10.0))),
+                              ("1 +".to_string(), None)));
+    }
+
+    #[test]
+    fn logic_short_circuit() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"0 && sqrt(-1)".to_string()), Ok(Some(0.0)));
+        assert_eq!(interp.eval_expression(&"1 || sqrt(-1)".to_string()), Ok(Some(1.0)));
+        assert!(interp.eval_expression(&"1 && sqrt(-1)".to_string()).is_err());
+    }
+
+    #[test]
+    fn logic_precedence() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"1 || 0 && 0".to_string()), Ok(Some(1.0)));
+        assert_eq!(interp.eval_expression(&"0 && 0 || 1".to_string()), Ok(Some(1.0)));
+        assert_eq!(interp.eval_expression(&"1 && 2 - 2".to_string()), Ok(Some(0.0)));
+    }
+
+    #[test]
+    fn deeply_nested() {
+        let mut interp = Interpreter::new();
+        let mut eq = String::new();
+        for _ in 0..100000 {
+            eq.push('(');
+        }
+        eq.push('1');
+        for _ in 0..100000 {
+            eq.push(')');
+        }
+        let err = interp.eval_expression(&eq).unwrap_err();
+        assert_eq!(err.desc, "Expression too deeply nested");
+    }
+
+    #[test]
+    fn long_chain_eval() {
+        let mut interp = Interpreter::new();
+        let mut eq = String::new();
+        for _ in 0..50000 {
+            eq.push_str("1+");
+        }
+        eq.push('1');
+        assert_eq!(interp.eval_expression(&eq), Ok(Some(50001.0)));
+        let eq = format!("1{}", "*6/3/2".repeat(20000));
+        assert_eq!(interp.eval_expression(&eq), Ok(Some(1.0)));
+    }
+
+    #[test]
+    fn eval_depth_limit() {
+        // tests run on threads with the default stack size, so going right up to the limit must
+        // not overflow it
+        let mut interp = Interpreter::new();
+        let eq = format!("0{}", "!".repeat(510));
+        assert_eq!(interp.eval_expression(&eq), Ok(Some(1.0)));
+        let eq = format!("0{}", "!".repeat(512));
+        let err = interp.eval_expression(&eq).unwrap_err();
+        assert_eq!(err.desc, "Expression too deeply nested");
+    }
+
+    #[test]
+    fn scientific_notation() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"2e3".to_string()), Ok(Some(2000.0)));
+        assert_eq!(interp.eval_expression(&"2e-3".to_string()), Ok(Some(0.002)));
+    }
+
+    #[test]
+    fn euler_constant() {
+        let mut interp = Interpreter::new();
+        let e = interp.eval_expression(&"e".to_string()).unwrap().unwrap();
+        assert_eq!(e, f64::consts::E);
+        assert_eq!(interp.eval_expression(&"exp(1)".to_string()), Ok(Some(e)));
+        assert_eq!(interp.eval_expression(&"2 e".to_string()), Ok(Some(2.0 * e)));
+        assert_eq!(interp.eval_expression(&"2e".to_string()), Ok(Some(2.0 * e)));
+    }
+
+    #[test]
+    fn undo_assignment() {
+        let mut interp = Interpreter::new();
+        interp.eval_expression(&"x = 1".to_string()).unwrap();
+        interp.eval_expression(&"x = 2".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"undo".to_string()), Ok(None));
+        assert_eq!(interp.eval_expression(&"x".to_string()), Ok(Some(1.0)));
+    }
+
+    #[test]
+    fn multi_assign() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"a, b = 3, 4".to_string()), Ok(None));
+        assert_eq!(interp.eval_expression(&"a".to_string()), Ok(Some(3.0)));
+        assert_eq!(interp.eval_expression(&"b".to_string()), Ok(Some(4.0)));
+        // the whole assignment is undone at once
+        interp.eval_expression(&"a, b = 5, 6".to_string()).unwrap();
+        interp.eval_expression(&"undo".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"a * 10 + b".to_string()), Ok(Some(34.0)));
+        // the values are evaluated before anything is assigned
+        interp.eval_expression(&"a, b = b, a".to_string()).unwrap();
+        assert_eq!(interp.eval_expression(&"a * 10 + b".to_string()), Ok(Some(43.0)));
+        // a failing value leaves every variable unchanged
+        assert!(interp.eval_expression(&"a, b = 1, c".to_string()).is_err());
+        assert_eq!(interp.eval_expression(&"a".to_string()), Ok(Some(4.0)));
+        let err = interp.eval_expression(&"a, b = 1".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((7, 8)));
+    }
+
+    #[test]
+    fn undo_repeatedly() {
+        let mut interp = Interpreter::new();
+        interp.eval_expression(&"x = 1".to_string()).unwrap();
+        interp.eval_expression(&"5".to_string()).unwrap();
+        interp.eval_expression(&"reset".to_string()).unwrap();
+        interp.eval_expression(&"undo".to_string()).unwrap();
+        interp.eval_expression(&"undo".to_string()).unwrap();
+        interp.eval_expression(&"undo".to_string()).unwrap();
+        assert!(interp.eval_expression(&"undo".to_string()).is_err());
+        assert_eq!(interp.eval_expression(&"ans".to_string()), Ok(Some(0.0)));
+        assert!(interp.eval_expression(&"x".to_string()).is_err());
+    }
+
+    #[test]
+    fn precision() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"precision 3".to_string()), Ok(None));
+        assert_eq!(interp.format_result(1.0 / 3.0), "0.333");
+        assert_eq!(interp.eval_expression(&"precision 0".to_string()), Ok(None));
+        assert_eq!(interp.format_result(41.9), "42");
+    }
+
+    #[test]
+    fn invalid_precision() {
+        let mut interp = Interpreter::new();
+        let err = interp.eval_expression(&"precision -1".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((10, 12)));
+        assert!(interp.eval_expression(&"precision x".to_string()).is_err());
+    }
+
+    #[test]
+    fn show_modes() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"show fraction".to_string()), Ok(None));
+        assert_eq!(interp.format_result(0.75), "3/4");
+        assert_eq!(interp.eval_expression(&"show percent".to_string()), Ok(None));
+        assert_eq!(interp.format_result(0.75), "75%");
+        assert_eq!(interp.eval_expression(&"show decimal".to_string()), Ok(None));
+        assert_eq!(interp.format_result(0.75), "0.75");
+        assert!(interp.eval_expression(&"show hex".to_string()).is_err());
+    }
+
+    #[test]
+    fn batch() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"x = 2; x + 1; ans * 2".to_string()), Ok(Some(6.0)));
+        assert_eq!(interp.eval_expression(&"1;".to_string()), Ok(Some(1.0)));
+    }
+
+    #[test]
+    fn prev_in_batch() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_expression(&"2;3;prev+prev2".to_string()), Ok(Some(5.0)));
+        assert_eq!(interp.eval_expression(&"4;prev1*2".to_string()), Ok(Some(8.0)));
+    }
+
+    #[test]
+    fn prev_out_of_range() {
+        let mut interp = Interpreter::new();
+        let err = interp.eval_expression(&"2;prev2".to_string()).unwrap_err();
+        assert_eq!(err.span, Some((2, 7)));
+        assert!(interp.eval_expression(&"prev".to_string()).is_err());
+    }
+
+    #[test]
+    fn exact_factorial() {
+        let mut interp = Interpreter::new();
+        let fact = interp.eval_expression(&"20!".to_string()).unwrap().unwrap();
+        assert_eq!(interp.format_result(fact), "2432902008176640000");
+        assert_eq!(interp.eval_expression(&"25!".to_string()),
+                   Ok(Some(15511210043330985984000000u128 as f64)));
+        assert_eq!(interp.eval_expression(&"0!".to_string()), Ok(Some(1.0)));
+    }
+
+    #[test]
+    fn large_factorial() {
+        let mut interp = Interpreter::new();
+        let fact = interp.eval_expression(&"40!".to_string()).unwrap().unwrap();
+        assert!((fact / 8.159152832478977e47 - 1.0).abs() < 1e-12);
+        assert_eq!(interp.eval_expression(&"171!".to_string()), Ok(Some(f64::INFINITY)));
+    }
+
+    #[test]
+    fn variance() {
+        let mut interp = Interpreter::new();
+        let eq = "variance(2, 4, 4, 4, 5, 5, 7, 9)".to_string();
+        assert_eq!(interp.eval_expression(&eq), Ok(Some(4.0)));
+    }
+
+    #[test]
+    fn stddev() {
+        let mut interp = Interpreter::new();
+        let eq = "stddev(2, 4, 4, 4, 5, 5, 7, 9)".to_string();
+        assert_eq!(interp.eval_expression(&eq), Ok(Some(2.0)));
+    }
+
+    #[test]
+    fn stddev_single_arg() {
+        let mut interp = Interpreter::new();
+        let eq = "stddev(2)".to_string();
+        assert!(interp.eval_expression(&eq).is_err());
+    }
+}
@@ -1,22 +1,88 @@
 use std::str::Chars;
 use std::iter::Peekable;
 use errors::{CalcrResult, CalcrError};
-use token::Token;
+use token::{Token, TokVal};
 use token::TokVal::*;
 use token::OpKind::*;
 use token::DelimKind::*;
 
+/// Options changing what input the lexer accepts
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LexerOptions {
+    /// Reject all non-ASCII input, suggesting ASCII spellings where possible
+    pub ascii_only: bool,
+    /// Lex `^` as bitwise xor instead of as a power, which can then only be written as `**`
+    pub caret_xor: bool,
+    /// Use `,` as the decimal separator and `.` to group digits, e.g. `1.000,5`, in which case
+    /// arguments are separated by `;` instead of `,`
+    pub decimal_comma: bool,
+}
+
+impl Default for LexerOptions {
+    fn default() -> LexerOptions {
+        LexerOptions::new()
+    }
+}
+
+impl LexerOptions {
+    pub fn new() -> LexerOptions {
+        LexerOptions {
+            ascii_only: false,
+            caret_xor: false,
+            decimal_comma: false,
+        }
+    }
+}
+
 pub fn lex_equation(eq: &String) -> CalcrResult<Vec<Token>> {
+    lex_equation_with_options(eq, LexerOptions::new())
+}
+
+pub fn lex_equation_with_options(eq: &String, options: LexerOptions) -> CalcrResult<Vec<Token>> {
     let mut lexer = Lexer {
         pos: 0,
         iter: eq.chars().peekable(),
+        options: options,
+        delim_depth: 0,
     };
     lexer.lex_expression()
 }
 
+/// Returns the ASCII way of writing `ch`, if `ch` has any meaning to the lexer
+fn ascii_spelling(ch: char) -> Option<&'static str> {
+    match ch {
+        '√' => Some("sqrt"),
+        'π' => Some("pi"),
+        'ϕ' => Some("phi"),
+        '°' => Some("* pi / 180"),
+        _ => None,
+    }
+}
+
+/// Parses a whole number written with a suffix giving its base, e.g. `01010b`, `0ffh` or `017o`
+///
+/// To tell these apart from implicit multiplication, `word` must start with a 0 followed by the
+/// digits, so `2h` is 2 times `h` and `ffh` is a name, while `0ffh` is 255.
+fn parse_base_suffix(word: &str) -> Option<f64> {
+    if !word.starts_with('0') || word.len() < 3 {
+        return None;
+    }
+    let (digits, suffix) = word[1..].split_at(word.len() - 2);
+    let radix = match suffix {
+        "b" => 2,
+        "o" => 8,
+        "h" => 16,
+        _ => return None,
+    };
+    u64::from_str_radix(digits, radix).ok().map(|num| num as f64)
+}
+
 pub struct Lexer<'a> {
     pos: usize,
     iter: Peekable<Chars<'a>>,
+    options: LexerOptions,
+    /// How many delimiters are currently open, used to tell argument separators from batches
+    delim_depth: usize,
 }
 
 impl<'a> Lexer<'a> {
@@ -25,33 +91,106 @@ impl<'a> Lexer<'a> {
         loop {
             self.consume_whitespace();
             let tok = match self.peek_char() {
-                Some(ch) if ch.is_numeric() => try!(self.lex_number()),
-                Some(ch) if ch.is_alphabetic() => try!(self.lex_name()),
+                Some(ch) if self.options.ascii_only && !ch.is_ascii() => {
+                    let desc = match ascii_spelling(ch) {
+                        Some(spelling) => format!("Non-ASCII char: {} (use {} instead)", ch, spelling),
+                        None => format!("Non-ASCII char: {}", ch),
+                    };
+                    return Err(CalcrError {
+                        desc: desc,
+                        span: Some((self.pos, self.pos + 1)),
+                    });
+                },
+                Some(ch) if ch.is_numeric() || self.next_is_fraction() => try!(self.lex_number()),
+                Some(ch) if ch.is_alphabetic() || ch == '_' => try!(self.lex_name()),
                 Some(_) => try!(self.lex_single_char()),
                 None => break,
             };
+            match tok.val {
+                OpenDelim(_) => self.delim_depth += 1,
+                CloseDelim(_) => self.delim_depth = self.delim_depth.saturating_sub(1),
+                _ => {},
+            }
             out.push(tok);
         }
         Ok(out)
     }
 
     fn lex_number(&mut self) -> CalcrResult<Token> {
-        let num_str = self.consume_while(|ch| ch.is_numeric() || ch == '.');
+        let start = self.pos;
+        let word: String = self.iter.clone().take_while(|ch| ch.is_ascii_alphanumeric()).collect();
+        if let Some(num) = parse_base_suffix(&word) {
+            for _ in 0..word.len() {
+                self.consume_char();
+            }
+            return Ok(Token {
+                val: Num(num),
+                span: (start, self.pos),
+            });
+        }
+        let mut num_str = if self.options.decimal_comma {
+            try!(self.lex_decimal_comma_digits())
+        } else {
+            self.consume_while(|ch| ch.is_numeric() || ch == '.')
+        };
+        if let Some(idx) = num_str.chars()
+                                  .enumerate()
+                                  .filter(|&(_, ch)| ch == '.')
+                                  .nth(1)
+                                  .map(|(idx, _)| idx) {
+            return Err(CalcrError {
+                desc: "Number has multiple decimal points".to_string(),
+                span: Some((start + idx, start + idx + 1)),
+            });
+        }
+        // An `e` is only part of the number if an exponent follows it, so `2e` is left as the
+        // number 2 followed by the constant e
+        if self.next_is_exponent() {
+            num_str.push(self.consume_char());
+            if self.peek_char() == Some('+') || self.peek_char() == Some('-') {
+                num_str.push(self.consume_char());
+            }
+            num_str.push_str(&self.consume_while(|ch| ch.is_digit(10)));
+        }
         if let Ok(num) = num_str.parse::<f64>() {
             Ok(Token {
                 val: Num(num),
-                span: (self.pos - num_str.len(), self.pos),
+                span: (start, self.pos),
             })
         } else {
             Err(CalcrError {
                 desc: format!("Invalid number: {}", num_str),
-                span: Some((self.pos - num_str.len(), self.pos)),
+                span: Some((start, self.pos)),
             })
         }
     }
 
+    /// Consumes the digits of a number written with a decimal comma, e.g. `1.000,5`, and returns
+    /// them with a decimal point instead, e.g. `1000.5`
+    fn lex_decimal_comma_digits(&mut self) -> CalcrResult<String> {
+        let mut out = self.consume_while(|ch| ch.is_digit(10));
+        while self.peek_char() == Some('.') {
+            let dot_pos = self.pos;
+            self.consume_char();
+            let group = self.consume_while(|ch| ch.is_digit(10));
+            if out.is_empty() || group.len() != 3 {
+                return Err(CalcrError {
+                    desc: "Digits must be grouped in threes when `.` is a separator".to_string(),
+                    span: Some((dot_pos, self.pos)),
+                });
+            }
+            out.push_str(&group);
+        }
+        if self.next_is_fraction() {
+            self.consume_char();
+            out.push('.');
+            out.push_str(&self.consume_while(|ch| ch.is_digit(10)));
+        }
+        Ok(out)
+    }
+
     fn lex_name(&mut self) -> CalcrResult<Token> {
-        let name_str = self.consume_while(|ch| ch.is_alphabetic() || ch.is_numeric());
+        let name_str = self.consume_while(|ch| ch.is_alphabetic() || ch.is_numeric() || ch == '_');
         let len = name_str.chars().count();
         Ok(Token {
             val: Name(name_str),
@@ -63,11 +202,20 @@ impl<'a> Lexer<'a> {
         let val = match self.consume_char() {
             '+' => Op(Plus),
             '-' => Op(Minus),
+            '*' if self.peek_char() == Some('*') => return Ok(self.lex_pair(Op(Pow))),
             '*' => Op(Mult),
             '/' => Op(Div),
+            '^' if self.options.caret_xor => Op(Xor),
             '^' => Op(Pow),
+            '!' if self.peek_char() == Some('=') => return Ok(self.lex_pair(Op(NotEqual))),
             '!' => Op(Fact),
+            '°' => Op(Deg),
+            '=' if self.peek_char() == Some('=') => return Ok(self.lex_pair(Op(Equal))),
             '=' => Op(Assign),
+            '<' if self.peek_char() == Some('=') => return Ok(self.lex_pair(Op(LessEq))),
+            '<' => Op(Less),
+            '>' if self.peek_char() == Some('=') => return Ok(self.lex_pair(Op(GreaterEq))),
+            '>' => Op(Greater),
             '√' => Name("sqrt".to_string()),
             '(' => OpenDelim(Paren),
             '[' => OpenDelim(Bracket),
@@ -75,7 +223,21 @@ impl<'a> Lexer<'a> {
             ')' => CloseDelim(Paren),
             ']' => CloseDelim(Bracket),
             '}' => CloseDelim(Brace),
+            '⌊' => OpenDelim(Floor),
+            '⌋' => CloseDelim(Floor),
+            '⌈' => OpenDelim(Ceil),
+            '⌉' => CloseDelim(Ceil),
+            '&' if self.peek_char() == Some('&') => return Ok(self.lex_pair(Op(And))),
+            '|' if self.peek_char() == Some('|') => return Ok(self.lex_pair(Op(Or))),
             '|' => AbsDelim,
+            ',' if self.options.decimal_comma => return Err(CalcrError {
+                desc: "Arguments are separated by `;` when using decimal commas".to_string(),
+                span: Some((self.pos - 1, self.pos)),
+            }),
+            ',' => Comma,
+            // within delimiters a semicolon separates arguments, as the comma is taken
+            ';' if self.options.decimal_comma && self.delim_depth > 0 => Comma,
+            ';' => Semicolon,
             ch => return Err(CalcrError {
                 desc: format!("Invalid char: {}", ch),
                 span: Some((self.pos - 1, self.pos)),
@@ -87,11 +249,43 @@ impl<'a> Lexer<'a> {
         })
     }
 
+    /// Consumes the second `char` of a two `char` token, and returns the whole token as `val`
+    fn lex_pair(&mut self, val: TokVal) -> Token {
+        self.consume_char();
+        Token {
+            val: val,
+            span: (self.pos - 2, self.pos),
+        }
+    }
+
     /// Peeks at the next `char` and returns `Some` if one was found, or `None` if none are left
     fn peek_char(&mut self) -> Option<char> {
         self.iter.peek().map(|ch| *ch)
     }
 
+    /// Checks whether the next `char`s are a decimal point followed by a digit, e.g. `.5`
+    fn next_is_fraction(&self) -> bool {
+        let point = if self.options.decimal_comma { ',' } else { '.' };
+        let mut iter = self.iter.clone();
+        iter.next() == Some(point) && iter.next().is_some_and(|ch| ch.is_ascii_digit())
+    }
+
+    /// Checks whether the next `char`s are the exponent of a number in scientific notation
+    ///
+    /// That is an `e` followed by at least one digit, optionally with a sign in between.
+    fn next_is_exponent(&self) -> bool {
+        let mut iter = self.iter.clone();
+        match iter.next() {
+            Some('e') | Some('E') => {},
+            _ => return false,
+        }
+        match iter.next() {
+            Some('+') | Some('-') => iter.next().is_some_and(|ch| ch.is_ascii_digit()),
+            Some(ch) => ch.is_ascii_digit(),
+            None => false,
+        }
+    }
+
     /// Consumes a `char` - thereby advanding `pos` - and returns it
     ///
     /// # Panics
@@ -99,7 +293,7 @@ impl<'a> Lexer<'a> {
     fn consume_char(&mut self) -> char {
         let ch = self.iter.next();
         self.pos += 1;
-        ch.unwrap().to_lowercase().next().unwrap()
+        ch.unwrap()
     }
 
     /// Consumes `char`s long as `pred` returns true and we are not eof
@@ -125,7 +319,7 @@ impl<'a> Lexer<'a> {
 
 #[cfg(test)]
 mod tests {
-    use super::lex_equation;
+    use super::{lex_equation, lex_equation_with_options, LexerOptions};
     use token::Token;
     use token::TokVal::*;
     use token::OpKind::*;
@@ -145,6 +339,172 @@ mod tests {
         assert_eq!(toks, Ok(vec!(Token { val: Num(2.0), span: (0, 1) })));
     }
 
+    #[test]
+    fn degree_sign() {
+        let eq = "90°".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(90.0), span: (0, 2) },
+                                 Token { val: Op(Deg), span: (2, 3) })));
+    }
+
+    #[test]
+    fn caret_modes() {
+        let eq = "2**3^4".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(2.0), span: (0, 1) },
+                                 Token { val: Op(Pow), span: (1, 3) },
+                                 Token { val: Num(3.0), span: (3, 4) },
+                                 Token { val: Op(Pow), span: (4, 5) },
+                                 Token { val: Num(4.0), span: (5, 6) })));
+        let mut options = LexerOptions::new();
+        options.caret_xor = true;
+        let toks = lex_equation_with_options(&eq, options);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(2.0), span: (0, 1) },
+                                 Token { val: Op(Pow), span: (1, 3) },
+                                 Token { val: Num(3.0), span: (3, 4) },
+                                 Token { val: Op(Xor), span: (4, 5) },
+                                 Token { val: Num(4.0), span: (5, 6) })));
+    }
+
+    #[test]
+    fn base_suffix() {
+        let eq = "01010b + 0ffh + 017o".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(10.0), span: (0, 6) },
+                                 Token { val: Op(Plus), span: (7, 8) },
+                                 Token { val: Num(255.0), span: (9, 13) },
+                                 Token { val: Op(Plus), span: (14, 15) },
+                                 Token { val: Num(15.0), span: (16, 20) })));
+    }
+
+    #[test]
+    fn base_suffix_needs_leading_zero() {
+        // otherwise these are implicit multiplications
+        for &(eq, num, name) in [("2h", 2.0, "h"), ("10h", 10.0, "h"), ("1b", 1.0, "b"),
+                                 ("3b", 3.0, "b"), ("0h", 0.0, "h")].iter() {
+            let toks = lex_equation(&eq.to_string());
+            let len = eq.len();
+            assert_eq!(toks, Ok(vec!(Token { val: Num(num), span: (0, len - 1) },
+                                     Token { val: Name(name.to_string()), span: (len - 1, len) })));
+        }
+    }
+
+    #[test]
+    fn invalid_base_suffix() {
+        // not valid digits for the base, so these are numbers followed by names
+        let eq = "2b 9o".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(2.0), span: (0, 1) },
+                                 Token { val: Name("b".to_string()), span: (1, 2) },
+                                 Token { val: Num(9.0), span: (3, 4) },
+                                 Token { val: Name("o".to_string()), span: (4, 5) })));
+        let eq = "ffh".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Name("ffh".to_string()), span: (0, 3) })));
+    }
+
+    #[test]
+    fn name_case() {
+        let eq = "Xy".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Name("Xy".to_string()), span: (0, 2) })));
+    }
+
+    #[test]
+    fn name_underscore() {
+        let eq = "N_A".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Name("N_A".to_string()), span: (0, 3) })));
+    }
+
+    #[test]
+    fn multiple_decimal_points() {
+        let eq = "1 + 1.2.3".to_string();
+        let err = lex_equation(&eq).unwrap_err();
+        assert_eq!(err.desc, "Number has multiple decimal points");
+        assert_eq!(err.span, Some((7, 8)));
+    }
+
+    #[test]
+    fn decimal_comma() {
+        let mut options = LexerOptions::new();
+        options.decimal_comma = true;
+        let lex = |eq: &str| lex_equation_with_options(&eq.to_string(), options);
+        assert_eq!(lex("1.000,5"), Ok(vec!(Token { val: Num(1000.5), span: (0, 7) })));
+        assert_eq!(lex("1.234.567"), Ok(vec!(Token { val: Num(1234567.0), span: (0, 9) })));
+        assert_eq!(lex(",5"), Ok(vec!(Token { val: Num(0.5), span: (0, 2) })));
+        assert_eq!(lex("f(1,5; 2); 3"),
+                   Ok(vec!(Token { val: Name("f".to_string()), span: (0, 1) },
+                           Token { val: OpenDelim(Paren), span: (1, 2) },
+                           Token { val: Num(1.5), span: (2, 5) },
+                           Token { val: Comma, span: (5, 6) },
+                           Token { val: Num(2.0), span: (7, 8) },
+                           Token { val: CloseDelim(Paren), span: (8, 9) },
+                           Token { val: Semicolon, span: (9, 10) },
+                           Token { val: Num(3.0), span: (11, 12) })));
+        assert_eq!(lex("1.5").unwrap_err().span, Some((1, 3)));
+        assert_eq!(lex("f(1, 2)").unwrap_err().span, Some((3, 4)));
+        // without the option, the usual convention applies
+        assert_eq!(lex_equation(&"1000.5".to_string()),
+                   Ok(vec!(Token { val: Num(1000.5), span: (0, 6) })));
+    }
+
+    #[test]
+    fn leading_decimal_point() {
+        let eq = ".5".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(0.5), span: (0, 2) })));
+    }
+
+    #[test]
+    fn trailing_decimal_point() {
+        let eq = "3.".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(3.0), span: (0, 2) })));
+    }
+
+    #[test]
+    fn lone_decimal_point() {
+        let eq = ".".to_string();
+        let err = lex_equation(&eq);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn scientific_notation() {
+        let eq = "2e3".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(2000.0), span: (0, 3) })));
+
+        let eq = "2E-3".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(0.002), span: (0, 4) })));
+
+        let eq = "1.5e+2".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(150.0), span: (0, 6) })));
+    }
+
+    #[test]
+    fn number_followed_by_e() {
+        let eq = "2e".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(2.0), span: (0, 1) },
+                                 Token { val: Name("e".to_string()), span: (1, 2) })));
+
+        let eq = "2 e".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(2.0), span: (0, 1) },
+                                 Token { val: Name("e".to_string()), span: (2, 3) })));
+
+        let eq = "2e-x".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(2.0), span: (0, 1) },
+                                 Token { val: Name("e".to_string()), span: (1, 2) },
+                                 Token { val: Op(Minus), span: (2, 3) },
+                                 Token { val: Name("x".to_string()), span: (3, 4) })));
+    }
+
     #[test]
     fn utf8() {
         let eq = "π𐍈".to_string();
@@ -171,6 +531,21 @@ mod tests {
                                  Token { val: Op(Pow), span: (5,6) })));
     }
 
+    #[test]
+    fn logic_ops() {
+        let eq = "&&||".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Op(And), span: (0,2) },
+                                 Token { val: Op(Or), span: (2,4) })));
+    }
+
+    #[test]
+    fn single_ampersand() {
+        let eq = "1 & 0".to_string();
+        let err = lex_equation(&eq);
+        assert!(err.is_err());
+    }
+
     #[test]
     fn delims() {
         let eq = "|()[]{}".to_string();
@@ -184,6 +559,52 @@ mod tests {
                                  Token { val: CloseDelim(Brace), span: (6,7) })));
     }
 
+    #[test]
+    fn rounding_delims() {
+        // the brackets are several bytes each, but spans count chars
+        let eq = "⌊π⌋⌈2⌉".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: OpenDelim(Floor), span: (0,1) },
+                                 Token { val: Name("π".to_string()), span: (1,2) },
+                                 Token { val: CloseDelim(Floor), span: (2,3) },
+                                 Token { val: OpenDelim(Ceil), span: (3,4) },
+                                 Token { val: Num(2.0), span: (4,5) },
+                                 Token { val: CloseDelim(Ceil), span: (5,6) })));
+    }
+
+    #[test]
+    fn comma() {
+        let eq = "(1,2)".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: OpenDelim(Paren), span: (0,1) },
+                                 Token { val: Num(1.0), span: (1,2) },
+                                 Token { val: Comma, span: (2,3) },
+                                 Token { val: Num(2.0), span: (3,4) },
+                                 Token { val: CloseDelim(Paren), span: (4,5) })));
+    }
+
+    #[test]
+    fn semicolon() {
+        let eq = "1;2".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(1.0), span: (0,1) },
+                                 Token { val: Semicolon, span: (1,2) },
+                                 Token { val: Num(2.0), span: (2,3) })));
+    }
+
+    #[test]
+    fn comparisons() {
+        let eq = "1<=2!=3<4".to_string();
+        let toks = lex_equation(&eq);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(1.0), span: (0,1) },
+                                 Token { val: Op(LessEq), span: (1,3) },
+                                 Token { val: Num(2.0), span: (3,4) },
+                                 Token { val: Op(NotEqual), span: (4,6) },
+                                 Token { val: Num(3.0), span: (6,7) },
+                                 Token { val: Op(Less), span: (7,8) },
+                                 Token { val: Num(4.0), span: (8,9) })));
+    }
+
     #[test]
     fn sqrt_single_char() {
         let eq = "√".to_string();
@@ -191,6 +612,31 @@ mod tests {
         assert_eq!(toks, Ok(vec!(Token { val: Name("sqrt".to_string()), span: (0,1) })));
     }
 
+    #[test]
+    fn ascii_only() {
+        let mut options = LexerOptions::new();
+        options.ascii_only = true;
+        let eq = "2*pi".to_string();
+        let toks = lex_equation_with_options(&eq, options);
+        assert_eq!(toks, Ok(vec!(Token { val: Num(2.0), span: (0, 1) },
+                                 Token { val: Op(Mult), span: (1, 2) },
+                                 Token { val: Name("pi".to_string()), span: (2, 4) })));
+
+        let eq = "2*π".to_string();
+        let err = lex_equation_with_options(&eq, options).unwrap_err();
+        assert_eq!(err.desc, "Non-ASCII char: π (use pi instead)");
+        assert_eq!(err.span, Some((2, 3)));
+
+        let eq = "√4".to_string();
+        let err = lex_equation_with_options(&eq, options).unwrap_err();
+        assert_eq!(err.desc, "Non-ASCII char: √ (use sqrt instead)");
+
+        // chars the lexer doesn't accept anyway get no suggestion
+        let eq = "2×3".to_string();
+        let err = lex_equation_with_options(&eq, options).unwrap_err();
+        assert_eq!(err.desc, "Non-ASCII char: ×");
+    }
+
     #[test]
     fn invalid_char() {
         let eq = "?".to_string();
@@ -0,0 +1,21 @@
+//! The calculator behind the `calcr` program
+//!
+//! Expressions are evaluated with an `interpreter::Interpreter`, or once with `eval`, while
+//! `parse` gives the syntax tree of an expression for use without the interpreter. For syntax
+//! highlighting, `classify_tokens` gives the `TokenCategory` of each token of an expression.
+
+extern crate unicode_width;
+
+pub mod ast;
+pub mod errors;
+pub mod interpreter;
+pub mod lexer;
+pub mod output;
+pub mod token;
+mod parser;
+mod rpn;
+mod number;
+
+pub use interpreter::eval;
+pub use parser::{parse, classify_tokens};
+pub use token::TokenCategory;
@@ -2,24 +2,57 @@ extern crate getopts;
 extern crate termios;
 extern crate libc;
 extern crate unicode_width;
+extern crate calcr;
 
 use std::env;
+use std::fs::File;
 use std::io;
-use getopts::Options;
+use std::io::{ErrorKind, Read, Write};
+use std::process;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+use getopts::{Options, Matches};
 use input::{InputHandler, PosixInputHandler, DefaultInputHandler};
-use input::InputCmd;
-use interpreter::Interpreter;
-
-mod parser;
-mod ast;
-mod errors;
-mod interpreter;
-mod lexer;
-mod token;
+use input::{InputCmd, CMD_PROMPT};
+use calcr::interpreter::{Answer, Interpreter, DEFAULT_MAX_FACTORIAL};
+use calcr::errors::{CalcrResult, CalcrError, printable};
+use calcr::output::OutputFormat;
+use calcr::lexer::{LexerOptions, lex_equation_with_options};
+
 mod input;
 
 const PROG_NAME: &'static str = "calcr";
 const VERSION: &'static str = "v0.7.0";
+/// The environment variable from which the initial value of `ans` is read
+const ANS_ENV_VAR: &'static str = "CALCR_ANS";
+/// The number of lines kept in the input history, unless overridden by `--history-size`
+const DEFAULT_HISTORY_SIZE: usize = 500;
+
+/// An equation given as an argument or read from a file
+struct Equation {
+    text: String,
+    /// The path of the file and the number of the line the equation was read from, if any
+    source: Option<(String, usize)>,
+}
+
+impl Equation {
+    fn new(text: String) -> Equation {
+        Equation {
+            text: text,
+            source: None,
+        }
+    }
+}
+
+/// How the equations given as arguments or in a file are evaluated and printed
+struct ArgumentOptions {
+    repeat: Option<usize>,
+    template: Option<String>,
+    /// Whether each equation is printed before its result, e.g. `> 2+2`
+    echo: bool,
+    debug_tokens: bool,
+    lexer_options: LexerOptions,
+}
 
 #[cfg(unix)]
 type TargetInputHandler = PosixInputHandler;
@@ -30,7 +63,45 @@ fn main() {
     let args: Vec<String> = env::args().collect();
     let mut opts = Options::new();
     opts.optflag("v", "version", "print the program version");
+    opts.optflag("", "bare", "with --version, print only the version number, e.g. 0.7.0");
+    opts.optflag("", "version-json", "print the program name and version as JSON");
     opts.optflag("h", "help", "print this and then exit");
+    opts.optflag("", "list-constants", "print the constants and their values and then exit");
+    opts.optflag("", "bool-words", "print the results of comparisons and logical operators as \
+                                    true or false");
+    opts.optflag("", "hexfloat", "print results in hexadecimal floating point notation");
+    opts.optflag("", "ascii", "reject non-ASCII input such as π or √");
+    opts.optflag("", "decimal-comma", "use , as the decimal separator and . to group digits, \
+                                       with ; separating function arguments");
+    opts.optflag("", "debug-tokens", "print the tokens of each equation before evaluating it");
+    opts.optopt("", "caret", "what ^ means: pow (the default) or xor, where ** is then power",
+                "MODE");
+    opts.optopt("f", "file", "read equations from FILE, one per line", "FILE");
+    opts.optflag("", "check", "only check that the equations given as arguments or in a file \
+                               can be parsed, without evaluating them");
+    opts.optflag("i", "interactive", "after evaluating the equations given as arguments, continue \
+                                      with their variables and result in the interactive mode");
+    opts.optflag("", "echo", "print each equation given as an argument or in a file before its \
+                              result");
+    opts.optflag("", "join", "evaluate all the equations given as arguments as a single equation");
+    opts.optflag("", "physics", "enable physical constants, such as c for the speed of light");
+    opts.optflag("", "strict-domain", "report an error when a result is not a number or is \
+                                       infinite");
+    opts.optflag("", "float", "compute everything with floating point numbers, instead of keeping \
+                               fractions such as 1/3 exact");
+    opts.optflag("", "rpn", "read expressions in reverse Polish notation, e.g. `3 4 +`");
+    opts.optopt("", "precision", "show results with N decimal places", "N");
+    opts.optopt("", "sigfigs", "show results with N significant figures", "N");
+    opts.optflag("", "precision-exact", "show every digit of results, instead of rounding to 12 \
+                                         significant figures to hide floating point noise");
+    opts.optopt("", "max-factorial", "the largest number to take the factorial of (default 10000)",
+                "N");
+    opts.optopt("", "history-size", "the number of input lines to remember (default 500)", "N");
+    opts.optopt("", "repeat", "evaluate each equation given as an argument N times, and print how \
+                               long it took", "N");
+    opts.optopt("", "output-format", "print the results of equations given as arguments using \
+                                      TEMPLATE, where {input}, {result}, {error} and {span} are \
+                                      replaced", "TEMPLATE");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -40,44 +111,229 @@ fn main() {
         }
     };
 
+    let (history_size, precision, sig_figs) = match (count_opt(&matches, "history-size"),
+                                                     count_opt(&matches, "precision"),
+                                                     count_opt(&matches, "sigfigs")) {
+        (Ok(history_size), Ok(precision), Ok(sig_figs)) => (history_size, precision, sig_figs),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let repeat = match count_opt(&matches, "repeat") {
+        Ok(Some(0)) => {
+            println!("--repeat must be at least 1");
+            return;
+        },
+        Ok(repeat) => repeat,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let max_factorial = match count_opt(&matches, "max-factorial") {
+        Ok(max_factorial) => max_factorial.map_or(DEFAULT_MAX_FACTORIAL, |max| max as u64),
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    if precision.is_some() && sig_figs.is_some() {
+        println!("Only one of --precision and --sigfigs can be given");
+        return;
+    }
+
+    let mut format = OutputFormat::new();
+    format.precision = precision;
+    format.sig_figs = sig_figs;
+    format.full_precision = matches.opt_present("precision-exact");
+    format.hex_float = matches.opt_present("hexfloat");
+    format.bool_words = matches.opt_present("bool-words");
+    format.decimal_comma = matches.opt_present("decimal-comma");
+    let mut lexer_options = LexerOptions::new();
+    lexer_options.ascii_only = matches.opt_present("ascii");
+    lexer_options.decimal_comma = format.decimal_comma;
+    lexer_options.caret_xor = match matches.opt_str("caret") {
+        Some(ref mode) if mode == "xor" => true,
+        Some(ref mode) if mode == "pow" => false,
+        None => false,
+        Some(_) => {
+            println!("--caret must be either pow or xor");
+            return;
+        }
+    };
+    let mut interp = Interpreter::new();
+    interp.set_clipboard(copy_to_clipboard);
+    interp.set_imaginary_results(true);
+    interp.set_format(format);
+    interp.set_lexer_options(lexer_options);
+    interp.set_rpn(matches.opt_present("rpn"));
+    interp.set_exact(!matches.opt_present("float"));
+    interp.set_physics(matches.opt_present("physics"));
+    interp.set_strict_domain(matches.opt_present("strict-domain"));
+    interp.set_max_factorial(max_factorial);
+    let debug_tokens = matches.opt_present("debug-tokens");
+    let arg_options = ArgumentOptions {
+        repeat: repeat,
+        template: matches.opt_str("output-format"),
+        echo: matches.opt_present("echo"),
+        debug_tokens: debug_tokens,
+        lexer_options: lexer_options,
+    };
+    if let Some(ans) = env::var(ANS_ENV_VAR).ok().and_then(|ans| ans.trim().parse::<f64>().ok()) {
+        interp.set_last_result(ans);
+    }
+
     if matches.opt_present("h") {
         println!("calcr - a small commandline calculator");
         print_usage(opts);
+    } else if matches.opt_present("version-json") {
+        println!("{}", version_json());
+    } else if matches.opt_present("v") && matches.opt_present("bare") {
+        println!("{}", bare_version());
     } else if matches.opt_present("v") {
         print_version();
-    } else if !matches.free.is_empty() {
-        let mut interp = Interpreter::new();
-        for eq in matches.free {
-            match interp.eval_expression(&eq) {
-                Ok(Some(num)) => println!("{}", num),
-                Err(e) => {
-                    println!("{}", e);
-                    e.print_location_highlight(&eq, true);
-                },
-                _ => {}, // do nothing
+    } else if matches.opt_present("list-constants") {
+        print!("{}", list_constants(&mut interp));
+    } else {
+        if !matches.free.is_empty() || matches.opt_present("file") {
+            let mut eqs = if matches.opt_present("join") {
+                vec!(Equation::new(matches.free.join(" ")))
+            } else {
+                matches.free.iter().map(|eq| Equation::new(eq.clone())).collect()
+            };
+            if let Some(path) = matches.opt_str("file") {
+                match read_lines(&path) {
+                    Ok(lines) => eqs.extend(lines),
+                    Err(e) => {
+                        writeln!(io::stderr(), "{}: could not read {}: {}", PROG_NAME, path, e)
+                            .ok();
+                        process::exit(1);
+                    }
+                }
+            }
+            if matches.opt_present("check") {
+                if !check_equations(&interp, &eqs) {
+                    process::exit(1);
+                }
+                return;
+            }
+            if let Err(e) = eval_arguments(&mut io::stdout(), &mut interp, &eqs, &arg_options) {
+                writeln!(io::stderr(), "{}: {}", PROG_NAME, e).ok();
+                process::exit(1);
+            }
+            if !matches.opt_present("interactive") {
+                return;
             }
         }
-    } else {
-        run_enviroment(TargetInputHandler::new()).ok().unwrap(); // TODO: Deal with the error case
+        // the REPL continues with the variables and `ans` left by any arguments
+        let history_size = history_size.unwrap_or(DEFAULT_HISTORY_SIZE);
+        let mut ih = new_input_handler(history_size);
+        let result = if ih.start().is_ok() {
+            run_enviroment(ih, interp, debug_tokens, lexer_options)
+        } else {
+            // e.g. if stdin is not a terminal, so fall back to reading plain lines
+            drop(ih);
+            run_enviroment(DefaultInputHandler::new(), interp, debug_tokens, lexer_options)
+        };
+        if let Err(e) = result {
+            writeln!(io::stderr(), "{}: {}", PROG_NAME, e).ok();
+            process::exit(1);
+        }
+    }
+}
+
+/// Evaluates the equations given as arguments, writing each result or error to `out`
+fn eval_arguments<W: Write>(out: &mut W, interp: &mut Interpreter, eqs: &[Equation],
+                            options: &ArgumentOptions) -> io::Result<()> {
+    for equation in eqs {
+        let eq = &equation.text;
+        if options.echo {
+            try!(writeln!(out, "> {}", eq));
+        }
+        if options.debug_tokens {
+            print_tokens(eq, options.lexer_options);
+        }
+        let result = match options.repeat {
+            Some(count) => time_evaluation(interp, eq, count),
+            None => interp.eval_answer(eq),
+        };
+        match (result, &options.template) {
+            (Ok(Some(answer)), &Some(ref template)) => {
+                let result = interp.format_answer(&answer);
+                try!(writeln!(out, "{}", fill_template(template, &[("input", eq),
+                                                                  ("result", &result),
+                                                                  ("error", ""),
+                                                                  ("span", "")])));
+            },
+            (Err(e), &Some(ref template)) => {
+                let span = e.span.map_or(String::new(), |(begin, end)| {
+                    format!("{}..{}", begin, end)
+                });
+                try!(writeln!(out, "{}", fill_template(template, &[("input", eq),
+                                                                  ("result", ""),
+                                                                  ("error", &e.desc),
+                                                                  ("span", &span)])));
+            },
+            (Ok(Some(answer)), &None) => {
+                try!(writeln!(out, "{}", interp.format_answer(&answer)))
+            },
+            (Ok(None), &None) => {
+                if let Some(text) = interp.command_output() {
+                    try!(writeln!(out, "{}", text));
+                }
+            },
+            (Err(e), &None) => try!(write_error(out, &e, equation)),
+            _ => {}, // do nothing
+        }
+    }
+    Ok(())
+}
+
+/// Parses the value of the option `name` as a non-negative whole number, if it was given
+fn count_opt(matches: &Matches, name: &str) -> Result<Option<usize>, String> {
+    match matches.opt_str(name) {
+        Some(val) => val.parse::<usize>()
+                        .map(|count| Some(count))
+                        .map_err(|_| format!("--{} must be a non-negative whole number", name)),
+        None => Ok(None),
     }
 }
 
-fn run_enviroment<H: InputHandler>(mut ih: H) -> io::Result<()> {
-    try!(ih.start());
+#[cfg(unix)]
+fn new_input_handler(history_size: usize) -> TargetInputHandler {
+    PosixInputHandler::new(history_size)
+}
+
+#[cfg(windows)]
+fn new_input_handler(_history_size: usize) -> TargetInputHandler {
+    DefaultInputHandler::new()
+}
+
+fn run_enviroment<H: InputHandler>(mut ih: H, mut interp: Interpreter, debug_tokens: bool,
+                                   lexer_options: LexerOptions) -> io::Result<()> {
     print_version();
-    let mut interp = Interpreter::new();
     loop {
-        ih.print_prompt();
+        let prompt = prompt(&interp);
+        ih.set_prompt(prompt.clone());
+        try!(ih.print_prompt());
         match ih.handle_input() {
             InputCmd::Quit => break,
             InputCmd::Equation(eq) => {
-                match interp.eval_expression(&eq) {
-                    Ok(Some(num)) => println!("{}", num.to_string()),
+                if debug_tokens {
+                    print_tokens(&eq, lexer_options);
+                }
+                match interp.eval_answer(&eq) {
+                    Ok(Some(answer)) => println!("{}", interp.format_answer(&answer)),
+                    Ok(None) => {
+                        if let Some(text) = interp.command_output() {
+                            println!("{}", text);
+                        }
+                    },
                     Err(e) => {
-                        e.print_location_highlight(&eq, false);
+                        e.print_location_highlight(&eq, Some(&prompt));
                         println!("{}", e);
                     },
-                    _ => {} // do nothing
                 }
             },
             InputCmd::None => {} // do nothing
@@ -87,11 +343,276 @@ fn run_enviroment<H: InputHandler>(mut ih: H) -> io::Result<()> {
     Ok(())
 }
 
+/// Returns the prompt of the interactive mode, which shows the current angle mode, e.g. `rad>> `
+fn prompt(interp: &Interpreter) -> String {
+    format!("{}{}", interp.angle_mode().name(), CMD_PROMPT)
+}
+
+/// Reads the non-empty lines of the file at `path`
+fn read_lines(path: &str) -> io::Result<Vec<Equation>> {
+    let mut contents = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut contents));
+    Ok(contents.lines()
+               .enumerate()
+               .filter(|&(_, line)| !line.trim().is_empty())
+               .map(|(idx, line)| Equation {
+                   text: line.to_string(),
+                   source: Some((path.to_string(), idx + 1)),
+               })
+               .collect())
+}
+
+/// Writes `e` followed by the location of the error in `eq`, which for an equation from a file
+/// starts with e.g. `formulas.txt:2:5: `
+fn write_error<W: Write>(out: &mut W, e: &CalcrError, eq: &Equation) -> io::Result<()> {
+    match eq.source {
+        Some((ref path, line)) => try!(writeln!(out, "{}:{}", path, e.at_line(line, &eq.text))),
+        None => try!(writeln!(out, "{}", e)),
+    }
+    try!(writeln!(out, "  {}", printable(&eq.text)));
+    writeln!(out, "{}", e.location_highlight(&eq.text, 2))
+}
+
+/// Checks that each of `eqs` can be parsed, printing the errors of those which can't
+///
+/// Returns whether all of them could be parsed.
+fn check_equations(interp: &Interpreter, eqs: &[Equation]) -> bool {
+    let mut all_ok = true;
+    for eq in eqs {
+        if let Err(e) = interp.check_expression(&eq.text) {
+            write_error(&mut io::stdout(), &e, eq).ok();
+            all_ok = false;
+        }
+    }
+    all_ok
+}
+
+/// Evaluates `eq` `count` times, printing the total and average time taken to stderr
+///
+/// Returns the result of the last evaluation, or the first error.
+fn time_evaluation(interp: &mut Interpreter, eq: &String, count: usize)
+                   -> CalcrResult<Option<Answer>> {
+    // the results are summed and printed, so the evaluations can't be optimized away
+    let mut sink = 0.0;
+    let mut result = Ok(None);
+    let start = Instant::now();
+    for _ in 0..count {
+        result = interp.eval_answer(eq);
+        match result {
+            Ok(Some(ref answer)) => sink += answer.value(),
+            Ok(None) => {},
+            Err(_) => return result,
+        }
+    }
+    let elapsed = start.elapsed();
+    let micros = elapsed.as_secs() as f64 * 1e6 + elapsed.subsec_nanos() as f64 / 1e3;
+    writeln!(io::stderr(), "{} evaluations in {:.0} µs, {:.3} µs each (sum of results: {})",
+             count, micros, micros / count as f64, sink).ok();
+    result
+}
+
+/// Replaces each `{name}` in `template` with the value of the field called `name`
+///
+/// Placeholders which don't name any of the fields are left as they are.
+fn fill_template(template: &str, fields: &[(&str, &str)]) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(begin) = rest.find('{') {
+        out.push_str(&rest[..begin]);
+        rest = &rest[begin..];
+        let field = rest.find('}').and_then(|end| {
+            fields.iter().find(|&&(name, _)| name == &rest[1..end]).map(|&(_, val)| (end, val))
+        });
+        match field {
+            Some((end, val)) => {
+                out.push_str(val);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Prints each token of `eq` on its own line, or nothing if `eq` could not be lexed
+fn print_tokens(eq: &String, options: LexerOptions) {
+    if let Ok(toks) = lex_equation_with_options(eq, options) {
+        for tok in toks {
+            println!("{}", tok);
+        }
+    }
+}
+
 fn print_usage(opts: Options) {
     let brief = format!("Usage:\n    {} [options...] [equation...]", PROG_NAME);
     println!("{}", opts.usage(&brief));
 }
 
+/// Writes each constant as its names followed by its value, e.g. `pi / π = 3.14159265359`
+fn list_constants(interp: &mut Interpreter) -> String {
+    let mut list = String::new();
+    for (names, val) in interp.constants() {
+        list.push_str(&format!("{} = {}\n", names.join(" / "), interp.format_result(val)));
+    }
+    list
+}
+
+/// The clipboard utilities tried in order, each as the program and its arguments
+#[cfg(target_os = "macos")]
+const CLIPBOARD_COMMANDS: &'static [&'static [&'static str]] = &[&["pbcopy"]];
+#[cfg(windows)]
+const CLIPBOARD_COMMANDS: &'static [&'static [&'static str]] = &[&["clip"]];
+#[cfg(not(any(target_os = "macos", windows)))]
+const CLIPBOARD_COMMANDS: &'static [&'static [&'static str]] = &[
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+    &["wl-copy"],
+];
+
+/// Places `text` on the clipboard by running the first clipboard utility which is installed
+fn copy_to_clipboard(text: &str) -> CalcrResult<()> {
+    let failed = |desc: String| CalcrError { desc: desc, span: None };
+    for cmd in CLIPBOARD_COMMANDS {
+        let mut child = match Command::new(cmd[0]).args(&cmd[1..]).stdin(Stdio::piped()).spawn() {
+            Ok(child) => child,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(failed(format!("Could not run {}: {}", cmd[0], e))),
+        };
+        // the utility only finishes once its input is closed, which happens when it is dropped
+        let written = child.stdin.take().unwrap().write_all(text.as_bytes());
+        let status = child.wait();
+        return match (written, status) {
+            (Ok(_), Ok(ref status)) if status.success() => Ok(()),
+            _ => Err(failed(format!("Could not copy the result with {}", cmd[0]))),
+        };
+    }
+    let names: Vec<_> = CLIPBOARD_COMMANDS.iter().map(|cmd| cmd[0]).collect();
+    Err(failed(format!("No clipboard utility was found, tried {}", names.join(", "))))
+}
+
 fn print_version() {
     println!("{} {}", PROG_NAME, VERSION);
-}
\ No newline at end of file
+}
+
+/// The version without the leading `v`, for scripts to compare
+fn bare_version() -> &'static str {
+    VERSION.trim_start_matches('v')
+}
+
+fn version_json() -> String {
+    format!("{{\"name\":\"{}\",\"version\":\"{}\"}}", PROG_NAME, bare_version())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+    use super::{fill_template, check_equations, list_constants, eval_arguments, version_json};
+    use super::{Equation, ArgumentOptions, read_lines, prompt};
+    use unicode_width::UnicodeWidthStr;
+    use calcr::interpreter::Interpreter;
+    use calcr::lexer::LexerOptions;
+
+    #[test]
+    fn output_template() {
+        let fields = [("input", "1 + 2"), ("result", "3")];
+        assert_eq!(fill_template("{input} => {result}", &fields), "1 + 2 => 3");
+        assert_eq!(fill_template("{result}{result}", &fields), "33");
+        // unknown or unclosed placeholders are kept
+        assert_eq!(fill_template("{x} {result} {", &fields), "{x} 3 {");
+        assert_eq!(fill_template("{{result}}", &fields), "{3}");
+    }
+
+    #[test]
+    fn check() {
+        let interp = Interpreter::new();
+        let eqs = |eqs: &[&str]| -> Vec<Equation> {
+            eqs.iter().map(|eq| Equation::new(eq.to_string())).collect()
+        };
+        assert!(check_equations(&interp, &eqs(&["1 / 0", "ln(-2)"])));
+        assert!(!check_equations(&interp, &eqs(&["2 * 3", "2 * * 3"])));
+    }
+
+    #[test]
+    fn file_lines() {
+        let path = env::temp_dir().join("calcr_file_lines_test.txt");
+        File::create(&path).unwrap().write_all(b"y = 2\n1 + x\n").unwrap();
+        let eqs = read_lines(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(eqs.len(), 2);
+        assert_eq!(eqs[1].source, Some((path.to_str().unwrap().to_string(), 2)));
+        let mut interp = Interpreter::new();
+        assert!(interp.eval_expression(&eqs[0].text).is_ok());
+        let err = interp.eval_expression(&eqs[1].text).unwrap_err();
+        assert_eq!(err.at_line(2, &eqs[1].text), "2:5: Undefined variable: x");
+    }
+
+    #[test]
+    fn prompt_highlight() {
+        let mut interp = Interpreter::new();
+        assert_eq!(prompt(&interp), "rad>> ");
+        interp.eval_expression(&"angle deg".to_string()).unwrap();
+        assert_eq!(prompt(&interp), "deg>> ");
+        // the highlight lines up with the input following the prompt
+        let eq = "1 + ?".to_string();
+        let err = interp.eval_expression(&eq).unwrap_err();
+        let line = format!("{}{}", prompt(&interp), eq);
+        let highlight = err.location_highlight(&eq, prompt(&interp).width());
+        assert_eq!(highlight.find('^'), line.find('?'));
+    }
+
+    #[test]
+    fn json_version() {
+        assert_eq!(version_json(), format!("{{\"name\":\"calcr\",\"version\":\"{}\"}}",
+                                           &super::VERSION[1..]));
+    }
+
+    fn options(echo: bool) -> ArgumentOptions {
+        ArgumentOptions {
+            repeat: None,
+            template: None,
+            echo: echo,
+            debug_tokens: false,
+            lexer_options: LexerOptions::new(),
+        }
+    }
+
+    #[test]
+    fn echo() {
+        let mut interp = Interpreter::new();
+        let eqs = [Equation::new("2+2".to_string()), Equation::new("1 + x".to_string())];
+        let mut out = Vec::new();
+        eval_arguments(&mut out, &mut interp, &eqs, &options(true)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "> 2+2\n4\n> 1 + x\nUndefined variable: x\n  1 + x\n      ^\n");
+    }
+
+    #[test]
+    fn arguments_keep_state() {
+        let mut interp = Interpreter::new();
+        let eqs = [Equation::new("x = 3".to_string()), Equation::new("x * 2".to_string())];
+        let mut out = Vec::new();
+        eval_arguments(&mut out, &mut interp, &eqs, &options(false)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "6\n");
+        // as when continuing in the interactive mode with `--interactive`
+        assert_eq!(interp.eval_expression(&"x + ans".to_string()), Ok(Some(9.0)));
+    }
+
+    #[test]
+    fn constants() {
+        let mut interp = Interpreter::new();
+        let list = list_constants(&mut interp);
+        assert!(list.contains("pi / π = 3.14159265359\n"));
+        assert!(list.contains("e = 2.71828182846\n"));
+        assert!(list.contains("phi / ϕ = 1.61803398875\n"));
+        assert!(!list.contains("c = "));
+        interp.set_physics(true);
+        assert!(list_constants(&mut interp).contains("c = 299792458\n"));
+    }
+}
@@ -0,0 +1,258 @@
+//! Numbers which are kept exact for as long as possible
+//!
+//! Whole numbers and fractions of them are stored exactly, so e.g. `1/3 + 1/3` is exactly `2/3`.
+//! As soon as something can't be computed exactly, such as `sqrt(2)` or an operation which would
+//! overflow, the number falls back to an `f64`.
+
+/// The largest whole number an `f64` can hold exactly, beyond which it is not treated as exact
+const MAX_EXACT_FLOAT: f64 = 9007199254740992.0;
+
+/// The largest exponent a power is computed exactly for, to avoid pointless repeated multiplying
+const MAX_EXACT_EXPONENT: i64 = 64;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Number {
+    Int(i64),
+    /// A fraction in lowest terms, where the denominator is always greater than 1
+    Rational(i64, i64),
+    Float(f64),
+}
+
+impl Number {
+    /// Converts `num` to an exact number if it is whole, and otherwise keeps it as an `f64`
+    pub fn from_f64(num: f64) -> Number {
+        if num.fract() == 0.0 && num.abs() <= MAX_EXACT_FLOAT {
+            Number::Int(num as i64)
+        } else {
+            Number::Float(num)
+        }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Number::Int(num) => num as f64,
+            Number::Rational(numer, denom) => numer as f64 / denom as f64,
+            Number::Float(num) => num,
+        }
+    }
+
+    /// Returns the numerator and denominator if the number is a fraction, but not if it is whole
+    pub fn fraction(self) -> Option<(i64, i64)> {
+        match self {
+            Number::Rational(numer, denom) => Some((numer, denom)),
+            _ => None,
+        }
+    }
+
+    /// Returns the number as a numerator and denominator, if it is exact
+    fn to_ratio(self) -> Option<(i64, i64)> {
+        match self {
+            Number::Int(num) => Some((num, 1)),
+            Number::Rational(numer, denom) => Some((numer, denom)),
+            Number::Float(_) => None,
+        }
+    }
+
+    /// Returns the number as a numerator and denominator like `to_ratio`, except that an `f64` is
+    /// taken to be exactly the decimal it is displayed as, e.g. 3/10 for 0.3
+    fn to_decimal_ratio(self) -> Option<(i64, i64)> {
+        let num = match self {
+            Number::Float(num) if num.is_finite() => num,
+            _ => return self.to_ratio(),
+        };
+        let shown = num.abs().to_string();
+        let (whole, fract) = match shown.find('.') {
+            Some(idx) => (&shown[..idx], &shown[idx + 1..]),
+            None => (&shown[..], ""),
+        };
+        let denom = 10i64.checked_pow(fract.len() as u32)?;
+        let digits = format!("{}{}", whole, fract).parse::<i64>().ok()?;
+        Some((if num < 0.0 { -digits } else { digits }, denom))
+    }
+
+    pub fn add(&self, rhs: &Number) -> Number {
+        self.exact_op(rhs, |(a, b), (c, d)| {
+            let (ad, cb) = (a.checked_mul(d), c.checked_mul(b));
+            (ad.and_then(|ad| cb.and_then(|cb| ad.checked_add(cb))), b.checked_mul(d))
+        }).unwrap_or_else(|| Number::Float(self.to_f64() + rhs.to_f64()))
+    }
+
+    pub fn sub(&self, rhs: &Number) -> Number {
+        self.exact_op(rhs, |(a, b), (c, d)| {
+            let (ad, cb) = (a.checked_mul(d), c.checked_mul(b));
+            (ad.and_then(|ad| cb.and_then(|cb| ad.checked_sub(cb))), b.checked_mul(d))
+        }).unwrap_or_else(|| Number::Float(self.to_f64() - rhs.to_f64()))
+    }
+
+    pub fn mul(&self, rhs: &Number) -> Number {
+        self.exact_op(rhs, |(a, b), (c, d)| (a.checked_mul(c), b.checked_mul(d)))
+            .unwrap_or_else(|| Number::Float(self.to_f64() * rhs.to_f64()))
+    }
+
+    /// Divides by `rhs`, where dividing by zero gives infinity or NaN like for `f64`s
+    pub fn div(&self, rhs: &Number) -> Number {
+        self.exact_op(rhs, |(a, b), (c, d)| (a.checked_mul(d), b.checked_mul(c)))
+            .unwrap_or_else(|| Number::Float(self.to_f64() / rhs.to_f64()))
+    }
+
+    /// The remainder of dividing by `rhs`, which has the sign of the number itself like `%` for
+    /// `f64`s
+    ///
+    /// Unlike the other operations, this takes an `f64` to be the decimal it is displayed as, so
+    /// that e.g. `0.3 mod 0.1` is 0 rather than almost 0.1.
+    pub fn rem(&self, rhs: &Number) -> Number {
+        self.decimal_op(rhs, |(a, b), (c, d)| {
+            let (ad, cb) = (a.checked_mul(d), c.checked_mul(b));
+            (ad.and_then(|ad| cb.and_then(|cb| ad.checked_rem(cb))), b.checked_mul(d))
+        }).unwrap_or_else(|| Number::Float(self.to_f64() % rhs.to_f64()))
+    }
+
+    /// Divides by `rhs` and rounds down to a whole number, taking an `f64` to be the decimal it
+    /// is displayed as like `rem`
+    pub fn div_floor(&self, rhs: &Number) -> Number {
+        match self.decimal_op(rhs, |(a, b), (c, d)| (a.checked_mul(d), b.checked_mul(c))) {
+            Some(Number::Rational(numer, denom)) => Number::Int(numer.div_euclid(denom)),
+            Some(num) => num,
+            None => Number::Float((self.to_f64() / rhs.to_f64()).floor()),
+        }
+    }
+
+    pub fn neg(&self) -> Number {
+        match *self {
+            Number::Int(num) => {
+                num.checked_neg().map_or(Number::Float(-(num as f64)), Number::Int)
+            },
+            Number::Rational(numer, denom) => {
+                numer.checked_neg().map_or(Number::Float(-self.to_f64()), |numer| {
+                    Number::Rational(numer, denom)
+                })
+            },
+            Number::Float(num) => Number::Float(-num),
+        }
+    }
+
+    /// Raises the number to the power `rhs`, which is only exact for whole exponents
+    pub fn pow(&self, rhs: &Number) -> Number {
+        match (self.to_ratio(), *rhs) {
+            (Some((numer, denom)), Number::Int(exp)) if exp.abs() <= MAX_EXACT_EXPONENT => {
+                let exp_abs = exp.unsigned_abs() as u32;
+                let pow = numer.checked_pow(exp_abs).and_then(|numer| {
+                    denom.checked_pow(exp_abs).map(|denom| (numer, denom))
+                });
+                let exact = match pow {
+                    Some((numer, denom)) if exp < 0 => ratio(denom, numer),
+                    Some((numer, denom)) => ratio(numer, denom),
+                    None => None,
+                };
+                exact.unwrap_or_else(|| Number::Float(self.to_f64().powf(rhs.to_f64())))
+            },
+            _ => Number::Float(self.to_f64().powf(rhs.to_f64())),
+        }
+    }
+
+    /// Applies `op` to the ratios of both numbers if they are exact, where `op` returns the
+    /// numerator and denominator of the result, or `None` for either if it overflowed
+    fn exact_op<F>(&self, rhs: &Number, op: F) -> Option<Number>
+        where F: Fn((i64, i64), (i64, i64)) -> (Option<i64>, Option<i64>) {
+        ratio_op(self.to_ratio(), rhs.to_ratio(), op)
+    }
+
+    /// Like `exact_op`, but taking an `f64` to be the decimal it is displayed as
+    fn decimal_op<F>(&self, rhs: &Number, op: F) -> Option<Number>
+        where F: Fn((i64, i64), (i64, i64)) -> (Option<i64>, Option<i64>) {
+        ratio_op(self.to_decimal_ratio(), rhs.to_decimal_ratio(), op)
+    }
+}
+
+/// Applies `op` to the ratios `lhs` and `rhs` for `exact_op`, if both are given
+fn ratio_op<F>(lhs: Option<(i64, i64)>, rhs: Option<(i64, i64)>, op: F) -> Option<Number>
+    where F: Fn((i64, i64), (i64, i64)) -> (Option<i64>, Option<i64>) {
+    match op(lhs?, rhs?) {
+        (Some(numer), Some(denom)) => ratio(numer, denom),
+        _ => None,
+    }
+}
+
+/// Creates the number `numer / denom` in lowest terms, or `None` if `denom` is zero or the result
+/// overflows
+fn ratio(numer: i64, denom: i64) -> Option<Number> {
+    if denom == 0 {
+        return None;
+    }
+    let divisor = gcd(numer, denom)?;
+    let (mut numer, mut denom) = (numer.checked_div(divisor)?, denom.checked_div(divisor)?);
+    if denom < 0 {
+        match (numer.checked_neg(), denom.checked_neg()) {
+            (Some(neg_numer), Some(neg_denom)) => {
+                numer = neg_numer;
+                denom = neg_denom;
+            },
+            _ => return None,
+        }
+    }
+    if denom == 1 {
+        Some(Number::Int(numer))
+    } else {
+        Some(Number::Rational(numer, denom))
+    }
+}
+
+/// The greatest common divisor of `a` and `b`, which is never 0 if `b` is not 0, or `None` if it
+/// overflows, e.g. for `i64::MIN` and -1
+fn gcd(a: i64, b: i64) -> Option<i64> {
+    let (mut a, mut b) = (a, b);
+    while b != 0 {
+        let rem = a.checked_rem(b)?;
+        a = b;
+        b = rem;
+    }
+    Some(a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Number;
+    use super::Number::*;
+
+    #[test]
+    fn exact_arithmetic() {
+        let third = Int(1).div(&Int(3));
+        assert_eq!(third, Rational(1, 3));
+        assert_eq!(third.add(&third), Rational(2, 3));
+        assert_eq!(third.mul(&Int(3)), Int(1));
+        assert_eq!(third.sub(&Rational(1, 2)), Rational(-1, 6));
+        assert_eq!(Int(6).div(&Int(-4)), Rational(-3, 2));
+        assert_eq!(Rational(2, 3).pow(&Int(-2)), Rational(9, 4));
+        assert_eq!(Rational(-1, 2).neg(), Rational(1, 2));
+    }
+
+    #[test]
+    fn float_fallback() {
+        assert_eq!(Int(1).div(&Int(0)), Float(1.0 / 0.0));
+        assert_eq!(Int(2).pow(&Rational(1, 2)), Float(2f64.sqrt()));
+        assert_eq!(Int(i64::max_value()).add(&Int(1)), Float(i64::max_value() as f64 + 1.0));
+        assert_eq!(Rational(1, 2).add(&Float(0.25)), Float(0.75));
+        assert_eq!(Number::from_f64(2.5), Float(2.5));
+        assert_eq!(Number::from_f64(-3.0), Int(-3));
+    }
+
+    #[test]
+    fn decimal_remainder() {
+        assert_eq!(Float(0.3).rem(&Float(0.1)), Int(0));
+        assert_eq!(Float(0.3).div_floor(&Float(0.1)), Int(3));
+        assert_eq!(Float(-7.5).rem(&Int(2)), Rational(-3, 2));
+        assert_eq!(Int(-7).div_floor(&Int(2)), Int(-4));
+        assert_eq!(Rational(1, 3).rem(&Rational(1, 4)), Rational(1, 12));
+        assert_eq!(Float(1e300).rem(&Int(7)), Float(1e300 % 7.0));
+        assert_eq!(Float(f64::INFINITY).div_floor(&Int(2)), Float(f64::INFINITY));
+    }
+
+    #[test]
+    fn overflowing_ratio() {
+        let min = Int(i64::MIN);
+        assert_eq!(min.div(&Int(-1)), Float(-(i64::MIN as f64)));
+        assert_eq!(min.mul(&Int(-1)), Float(-(i64::MIN as f64)));
+        assert_eq!(Int(-1).div(&min), Float(-1.0 / i64::MIN as f64));
+        assert_eq!(min.div(&Int(2)), Int(i64::MIN / 2));
+    }
+}
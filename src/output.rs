@@ -0,0 +1,336 @@
+use std::char;
+
+/// The largest denominator tried when showing results as fractions
+const MAX_DENOMINATOR: u64 = 10000;
+/// How close, relative to the result, a fraction must be to be shown instead of the result
+const FRACTION_TOLERANCE: f64 = 1e-9;
+/// The number of significant figures results are rounded to when no precision is given, which
+/// hides the noise of floating point arithmetic while keeping almost every real digit
+const AUTO_SIG_FIGS: usize = 12;
+
+/// The ways results can be displayed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DisplayMode {
+    /// As a decimal number, e.g. `0.75`
+    Decimal,
+    /// As a fraction, e.g. `3/4`, falling back to decimal if no close fraction exists
+    Fraction,
+    /// As a percentage, e.g. `75%`
+    Percent,
+}
+
+impl DisplayMode {
+    /// Returns the name of the mode, as given to the `show` command
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DisplayMode::Decimal => "decimal",
+            DisplayMode::Fraction => "fraction",
+            DisplayMode::Percent => "percent",
+        }
+    }
+}
+
+/// Settings controlling how results are formatted for display
+#[derive(Debug, PartialEq, Clone)]
+pub struct OutputFormat {
+    /// The number of decimal places to show, or `None` to show as many as needed
+    pub precision: Option<usize>,
+    /// The number of significant figures to show, which takes priority over `precision`
+    pub sig_figs: Option<usize>,
+    /// Whether to show the exact bits of results in hexadecimal floating point notation
+    pub hex_float: bool,
+    /// Whether to write the decimal separator as a comma, e.g. `0,5`
+    pub decimal_comma: bool,
+    /// Whether to show the results of comparisons and logical operators as `true` or `false`
+    pub bool_words: bool,
+    /// Whether to show every digit when no precision is given, rather than rounding to
+    /// `AUTO_SIG_FIGS` significant figures such that e.g. `0.1 + 0.2` is shown as `0.3`
+    pub full_precision: bool,
+    /// Whether to show results as decimals, fractions or percentages
+    pub mode: DisplayMode,
+}
+
+impl Default for OutputFormat {
+    fn default() -> OutputFormat {
+        OutputFormat::new()
+    }
+}
+
+impl OutputFormat {
+    pub fn new() -> OutputFormat {
+        OutputFormat {
+            precision: None,
+            sig_figs: None,
+            hex_float: false,
+            decimal_comma: false,
+            bool_words: false,
+            full_precision: false,
+            mode: DisplayMode::Decimal,
+        }
+    }
+
+    /// Formats `num` for display according to the current settings
+    pub fn format(&self, num: f64) -> String {
+        if self.hex_float {
+            return format_hex_float(num);
+        }
+        match self.mode {
+            DisplayMode::Decimal => self.format_decimal(num),
+            DisplayMode::Fraction => {
+                match to_fraction(num) {
+                    Some((numer, 1)) => numer.to_string(),
+                    Some((numer, denom)) => format!("{}/{}", numer, denom),
+                    None => self.format_decimal(num),
+                }
+            },
+            DisplayMode::Percent => format!("{}%", self.format_decimal(num * 100.0)),
+        }
+    }
+
+    fn format_decimal(&self, num: f64) -> String {
+        let out = if let Some(sig_figs) = self.sig_figs {
+            round_sig_figs(num, sig_figs).to_string()
+        } else {
+            match self.precision {
+                Some(precision) => format!("{:.*}", precision, num),
+                None if self.full_precision => num.to_string(),
+                None => round_noise(num).to_string(),
+            }
+        };
+        if self.decimal_comma {
+            out.replace('.', ",")
+        } else {
+            out
+        }
+    }
+}
+
+/// Formats the whole number `num` in `base`, which must be from 2 to 36
+///
+/// Bases 2, 8 and 16 are shown with the prefixes `0b`, `0o` and `0x`, while other bases are
+/// named after the digits, e.g. `zz (base 36)`.
+pub fn format_in_base(num: f64, base: u32) -> String {
+    let sign = if num < 0.0 { "-" } else { "" };
+    let mut left = num.abs() as u64;
+    let mut digits = Vec::new();
+    loop {
+        digits.push(char::from_digit((left % base as u64) as u32, base).unwrap());
+        left /= base as u64;
+        if left == 0 {
+            break;
+        }
+    }
+    let digits: String = digits.into_iter().rev().collect();
+    match base {
+        2 => format!("{}0b{}", sign, digits),
+        8 => format!("{}0o{}", sign, digits),
+        16 => format!("{}0x{}", sign, digits),
+        _ => format!("{}{} (base {})", sign, digits, base),
+    }
+}
+
+/// Rounds `num` to `sig_figs` significant figures, e.g. 1234.5 to 3 significant figures is 1230
+fn round_sig_figs(num: f64, sig_figs: usize) -> f64 {
+    if num == 0.0 || !num.is_finite() || sig_figs == 0 {
+        return num;
+    }
+    // the number of decimal places to keep, which is negative when rounding to tens, hundreds...
+    let places = sig_figs as i32 - 1 - num.abs().log10().floor() as i32;
+    if places >= 0 {
+        let scale = 10f64.powi(places);
+        (num * scale).round() / scale
+    } else {
+        let scale = 10f64.powi(-places);
+        (num / scale).round() * scale
+    }
+}
+
+/// Rounds `num` to `AUTO_SIG_FIGS` significant figures, but never past the decimal point
+fn round_noise(num: f64) -> f64 {
+    // very large numbers are always whole, so this also keeps the digit count below
+    if num.fract() == 0.0 || !num.is_finite() {
+        return num;
+    }
+    let whole_digits = num.abs().log10().floor() as i32 + 1;
+    let sig_figs = (whole_digits.max(0) as usize).max(AUTO_SIG_FIGS);
+    // going through a decimal string gives the closest `f64` to the rounded number, which is
+    // then shown without any noise
+    format!("{:.*e}", sig_figs - 1, num).parse().unwrap_or(num)
+}
+
+/// Finds the fraction closest to `num` with a denominator no larger than `MAX_DENOMINATOR`
+///
+/// This is done by computing the convergents of the continued fraction of `num`, and returns
+/// `None` if none of them are within `FRACTION_TOLERANCE` of `num`.
+fn to_fraction(num: f64) -> Option<(i64, u64)> {
+    if !num.is_finite() || num.abs() >= (1u64 << 53) as f64 {
+        return None;
+    }
+    let target = num.abs();
+    let (mut numer, mut prev_numer) = (1u64, 0u64);
+    let (mut denom, mut prev_denom) = (0u64, 1u64);
+    let mut x = target;
+    loop {
+        let whole = x.floor() as u64;
+        let next_numer = whole.saturating_mul(numer).saturating_add(prev_numer);
+        let next_denom = whole.saturating_mul(denom).saturating_add(prev_denom);
+        if next_denom > MAX_DENOMINATOR {
+            return None;
+        }
+        prev_numer = numer;
+        prev_denom = denom;
+        numer = next_numer;
+        denom = next_denom;
+        let approx = numer as f64 / denom as f64;
+        if (approx - target).abs() <= FRACTION_TOLERANCE * target.max(1.0) {
+            let numer = numer as i64;
+            return Some((if num < 0.0 { -numer } else { numer }, denom));
+        }
+        x = 1.0 / (x - whole as f64);
+    }
+}
+
+/// Formats `num` in hexadecimal floating point notation, like `%a` in C
+///
+/// Normal numbers are shown as `0x1.<mantissa>p<exponent>`, where the mantissa is in hex and the
+/// exponent is the power of two in decimal. This is exact, so e.g. `0.1` is `0x1.999999999999ap-4`.
+pub fn format_hex_float(num: f64) -> String {
+    if num.is_nan() {
+        return "nan".to_string();
+    }
+    let sign = if num.is_sign_negative() { "-" } else { "" };
+    if num.is_infinite() {
+        return format!("{}inf", sign);
+    }
+    let bits = num.to_bits();
+    let exp_bits = ((bits >> 52) & 0x7FF) as i64;
+    let mantissa = bits & ((1 << 52) - 1);
+    let (lead, exp) = if exp_bits == 0 {
+        // zero or subnormal, where there is no implicit leading 1
+        (0, if mantissa == 0 { 0 } else { -1022 })
+    } else {
+        (1, exp_bits - 1023)
+    };
+    let frac = format!("{:013x}", mantissa);
+    let frac = frac.trim_end_matches('0');
+    if frac.is_empty() {
+        format!("{}0x{}p{:+}", sign, lead, exp)
+    } else {
+        format!("{}0x{}.{}p{:+}", sign, lead, frac, exp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64;
+    use super::{OutputFormat, DisplayMode, format_hex_float, format_in_base};
+
+    #[test]
+    fn full_precision() {
+        let fmt = OutputFormat::new();
+        assert_eq!(fmt.format(0.25), "0.25");
+        assert_eq!(fmt.format(3.0), "3");
+    }
+
+    #[test]
+    fn auto_precision() {
+        let mut fmt = OutputFormat::new();
+        assert_eq!(fmt.format(0.1 + 0.2), "0.3");
+        assert_eq!(fmt.format(1.0 / 3.0), "0.333333333333");
+        assert_eq!(fmt.format(-2.0 / 3.0), "-0.666666666667");
+        assert_eq!(fmt.format(1.1e-20 + 2.2e-20), "0.000000000000000000033");
+        // the digits before the decimal point and distinct nearby values are kept
+        assert_eq!(fmt.format(123456789012.345), "123456789012");
+        assert_eq!(fmt.format(12345678901234.5), "12345678901234");
+        assert_eq!(fmt.format(1.00000000001), "1.00000000001");
+        assert_eq!(fmt.format(9007199254740993.0), "9007199254740992");
+        fmt.full_precision = true;
+        assert_eq!(fmt.format(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(fmt.format(1.0 / 3.0), "0.3333333333333333");
+    }
+
+    #[test]
+    fn fixed_precision() {
+        let mut fmt = OutputFormat::new();
+        fmt.precision = Some(2);
+        assert_eq!(fmt.format(1.0 / 3.0), "0.33");
+        fmt.precision = Some(0);
+        assert_eq!(fmt.format(2.7), "3");
+    }
+
+    #[test]
+    fn sig_figs() {
+        let mut fmt = OutputFormat::new();
+        fmt.sig_figs = Some(3);
+        assert_eq!(fmt.format(1234.5), "1230");
+        assert_eq!(fmt.format(0.001234), "0.00123");
+        assert_eq!(fmt.format(-9.996), "-10");
+        assert_eq!(fmt.format(123456789.0), "123000000");
+        assert_eq!(fmt.format(0.0), "0");
+        fmt.precision = Some(5);
+        assert_eq!(fmt.format(2.0 / 3.0), "0.667");
+    }
+
+    #[test]
+    fn decimal_comma() {
+        let mut fmt = OutputFormat::new();
+        fmt.decimal_comma = true;
+        assert_eq!(fmt.format(1000.5), "1000,5");
+        fmt.mode = DisplayMode::Percent;
+        assert_eq!(fmt.format(0.125), "12,5%");
+    }
+
+    #[test]
+    fn fraction_mode() {
+        let mut fmt = OutputFormat::new();
+        fmt.mode = DisplayMode::Fraction;
+        assert_eq!(fmt.format(0.75), "3/4");
+        assert_eq!(fmt.format(-1.0 / 3.0), "-1/3");
+        assert_eq!(fmt.format(7.0 / 4.0), "7/4");
+        assert_eq!(fmt.format(5.0), "5");
+        assert_eq!(fmt.format(0.0), "0");
+    }
+
+    #[test]
+    fn fraction_mode_fallback() {
+        let mut fmt = OutputFormat::new();
+        fmt.mode = DisplayMode::Fraction;
+        assert_eq!(fmt.format(f64::consts::PI), "3.14159265359");
+        assert_eq!(fmt.format(f64::INFINITY), "inf");
+    }
+
+    #[test]
+    fn percent_mode() {
+        let mut fmt = OutputFormat::new();
+        fmt.mode = DisplayMode::Percent;
+        assert_eq!(fmt.format(0.75), "75%");
+        assert_eq!(fmt.format(1.5), "150%");
+    }
+
+    #[test]
+    fn in_base() {
+        assert_eq!(format_in_base(10.0, 2), "0b1010");
+        assert_eq!(format_in_base(15.0, 8), "0o17");
+        assert_eq!(format_in_base(-255.0, 16), "-0xff");
+        assert_eq!(format_in_base(0.0, 16), "0x0");
+        assert_eq!(format_in_base(1295.0, 36), "zz (base 36)");
+    }
+
+    #[test]
+    fn hex_float() {
+        assert_eq!(format_hex_float(1.0), "0x1p+0");
+        assert_eq!(format_hex_float(2.0), "0x1p+1");
+        assert_eq!(format_hex_float(-0.5), "-0x1p-1");
+        assert_eq!(format_hex_float(0.1), "0x1.999999999999ap-4");
+        assert_eq!(format_hex_float(1.0 / 3.0), "0x1.5555555555555p-2");
+    }
+
+    #[test]
+    fn hex_float_special() {
+        assert_eq!(format_hex_float(0.0), "0x0p+0");
+        assert_eq!(format_hex_float(-0.0), "-0x0p+0");
+        assert_eq!(format_hex_float(5e-324), "0x0.0000000000001p-1022");
+        assert_eq!(format_hex_float(f64::INFINITY), "inf");
+        assert_eq!(format_hex_float(f64::NAN), "nan");
+    }
+}
@@ -1,36 +1,63 @@
 //! The parser is based on the following grammar
 //!
 //! Expression ==> Name "=" Equation
+//!             |  Name { "," Name } "=" Equation { "," Equation }
+//!                                          (with as many names as equations)
 //!             |  Equation
 //!
-//! Equation   ==> Product { "+" Product }
+//! Equation   ==> Conjunction { "||" Conjunction }
+//!
+//! Conjunction ==> Comparison { "&&" Comparison }
+//!
+//! Comparison ==> ExclusiveOr [ ( "<" | ">" | "<=" | ">=" | "==" | "!=" ) ExclusiveOr ]
+//!
+//! ExclusiveOr ==> Sum { "^" Sum }            (only with `LexerOptions::caret_xor`)
+//!
+//! Sum        ==> Product { "+" Product }
 //!             |  Product { "-" Product }
 //!
 //! Product    ==> Factor { "*" Factor }
 //!             |  Factor { "/" Factor }
+//!             |  Factor { "mod" Factor }
+//!             |  Factor { "div" Factor }    (so "mod" and "div" cannot be used as names)
+//!             |  Factor { Factor }         (only if the second factor starts with a name or
+//!                                           an opening delimiter, e.g. `2 pi` or `2(3 + 4)`)
 //!
 //! Factor     ==> "-" Factor
+//!             |  "+" Factor                  (which is the factor itself)
 //!             |  Exponent { "^" Factor }
 //!
-//! Exponent   ==> Number { "!" }
+//! Exponent   ==> Number { "!" | "°" }       (the power operator can also be written "**")
 //!
-//! Number     ==> Function OpenDelim Equation CloseDelim
+//! Number     ==> Function OpenDelim Arguments CloseDelim
+//!             |  Function Number            (only if the argument is a NumLiteral, Constant,
+//!                                            Name or Function, so `sin 2 + 3` is `sin(2) + 3`)
 //!             |  Constant
 //!             |  Name
 //!             |  "ans"
 //!             |  OpenDelim Equation CloseDelim
 //!             |  "|" Equation "|"
+//!             |  "⌊" Equation "⌋"              (the same as `floor(...)`)
+//!             |  "⌈" Equation "⌉"              (the same as `ceil(...)`)
 //!             |  NumLiteral
 //!
-//! Function   ==> "sin" | "cos" | "tan" | "asin" | "acos" | "atan" | "sqrt" | "abs" | "exp"
-//!             |  "ln" | "log"
+//! Arguments  ==> Equation [ "=" Equation ] { "," Equation }
+//!                                           (the "=" is only allowed in the first argument of
+//!                                            "solve", e.g. `solve(2x + 3 = 7, x)`)
 //!
-//! Constant   ==> "pi" | "π" | "e" | "phi" | "ϕ" | "ans"
+//! Function   ==> "sin" | "cos" | "tan" | "sec" | "csc" | "cot" | "asin" | "acos" | "atan"
+//!             |  "sqrt" | "abs" | "exp"
+//!             |  "ln" | "log" | "log_" Digits | "stddev" | "variance" | "frac" | "int"
+//!             |  "floor" | "ceil" | "gamma"
+//!             |  "hypot" | "dist" | "snap" | "tobase" | "solve" | "cf"
+//!
+//! Constant   ==> "pi" | "π" | "e" | "phi" | "ϕ" | "inf" | "nan" | "ans" | "prev" | "prev" Digits
 //!
 //! OpenDelim  ==> "(" | "[" | "{"
 //!
 //! CloseDelim ==> ")" | "]" | "}"
 
+use std::collections::HashSet;
 use std::vec::IntoIter;
 use std::iter::Peekable;
 use errors::{CalcrResult, CalcrError};
@@ -40,41 +67,263 @@ use ast::OpKind as AstOp;
 //use ast::AstBranch::*;
 use ast::FuncKind::*;
 use ast::ConstKind::*;
-use token::Token;
+use lexer::lex_equation;
+use token::{Token, Span, TokenCategory, DelimKind};
 use token::OpKind as TokOp;
 use token::TokVal;
 use token::TokVal::*;
 
+/// The default limit on how deeply delimiters and operators may be nested
+///
+/// A level of nesting can take over 10 KiB of stack in a debug build, so this leaves room to spare
+/// on a thread with the default 2 MiB stack.
+pub const DEFAULT_MAX_DEPTH: u32 = 150;
+
+/// Lexes and parses `input`, giving the syntax tree of the expression without evaluating it
+///
+/// Only the builtin functions can be called, and any other names are treated as variables.
+///
+/// ```
+/// use calcr::ast::{AstVal, OpKind};
+///
+/// let ast = calcr::parse("2+3*4").unwrap();
+/// assert_eq!(ast.val, AstVal::Op(OpKind::Plus));
+/// assert_eq!(ast.branches[0].val, AstVal::Num(2.0));
+/// let product = &ast.branches[1];
+/// assert_eq!(product.val, AstVal::Op(OpKind::Mult));
+/// assert_eq!(product.branches[0].val, AstVal::Num(3.0));
+/// assert_eq!(product.branches[1].val, AstVal::Num(4.0));
+/// ```
+pub fn parse(input: &str) -> CalcrResult<Ast> {
+    let toks = try!(lex_equation(&input.to_string()));
+    parse_tokens(toks)
+}
+
 pub fn parse_tokens(tokens: Vec<Token>) -> CalcrResult<Ast> {
-    let end_pos = tokens.last().and_then(|tok| Some(tok.span.1)).unwrap_or(0);
-    let mut parser = Parser {
-        iter: tokens.into_iter().peekable(),
-        paren_level: 0,
-        abs_level: 0,
-        end_pos: end_pos,
-    };
+    parse_tokens_with_max_depth(tokens, DEFAULT_MAX_DEPTH)
+}
+
+/// Parses `tokens`, failing with an error instead of overflowing the stack if the expression is
+/// nested more than `max_depth` levels deep
+pub fn parse_tokens_with_max_depth(tokens: Vec<Token>, max_depth: u32) -> CalcrResult<Ast> {
+    let mut parser = Parser::new(tokens, max_depth);
+    parser.parse_expression()
+}
+
+/// Parses `tokens`, where the names in `functions` are treated as callable like the builtin
+/// functions, rather than as variables
+pub fn parse_tokens_with_functions(tokens: Vec<Token>, functions: HashSet<String>)
+                                   -> CalcrResult<Ast> {
+    let mut parser = Parser::new(tokens, DEFAULT_MAX_DEPTH);
+    parser.functions = functions;
     parser.parse_expression()
 }
 
-fn get_builtin_name(name: &String) -> Option<AstVal> {
-    match name.as_ref() {
-        "ans" => Some(AstVal::LastResult),
-        "pi" | "π" => Some(AstVal::Const(Pi)),
-        "e" => Some(AstVal::Const(E)),
-        "phi" | "ϕ" => Some(AstVal::Const(Phi)),
-        "cos" => Some(AstVal::Func(Cos)),
-        "sin" => Some(AstVal::Func(Sin)),
-        "tan" => Some(AstVal::Func(Tan)),
-        "asin" => Some(AstVal::Func(Asin)),
-        "acos" => Some(AstVal::Func(Acos)),
-        "atan" => Some(AstVal::Func(Atan)),
-        "sqrt" | "√" => Some(AstVal::Func(Sqrt)),
-        "abs" => Some(AstVal::Func(Abs)),
-        "exp" => Some(AstVal::Func(Exp)),
-        "ln" => Some(AstVal::Func(Ln)),
-        "log" => Some(AstVal::Func(Log)),
-        _ => None
+/// Lexes `input` and classifies each token, without parsing it
+///
+/// This is meant for tools such as syntax highlighters, which want to know what the tokens are
+/// even if the input is not (yet) a valid expression.
+///
+/// ```
+/// use calcr::TokenCategory;
+///
+/// let toks = calcr::classify_tokens("sin(pi) + x").unwrap();
+/// assert_eq!(toks[0], ((0, 3), TokenCategory::Function));
+/// assert_eq!(toks[2], ((4, 6), TokenCategory::Constant));
+/// assert_eq!(toks[5], ((10, 11), TokenCategory::Name));
+/// ```
+pub fn classify_tokens(input: &str) -> CalcrResult<Vec<(Span, TokenCategory)>> {
+    let toks = try!(lex_equation(&input.to_string()));
+    Ok(toks.into_iter().map(|tok| {
+        let category = match tok.val {
+            Num(_) => TokenCategory::Number,
+            Op(_) => TokenCategory::Operator,
+            OpenDelim(_) | CloseDelim(_) | AbsDelim | Comma | Semicolon => {
+                TokenCategory::Delimiter
+            },
+            Name(ref name) => match get_builtin_name(name) {
+                Some(AstVal::Func(_)) => TokenCategory::Function,
+                Some(_) => TokenCategory::Constant,
+                None if log_base(name).is_some() => TokenCategory::Function,
+                None => TokenCategory::Name,
+            },
+        };
+        (tok.span, category)
+    }).collect())
+}
+
+/// A builtin function or constant, along with a description for `help`
+pub struct Builtin {
+    /// The names the builtin can be written as, where the first one is the main name
+    pub names: &'static [&'static str],
+    pub val: AstVal,
+    /// How the builtin is used, e.g. `sin(x)`
+    pub usage: &'static str,
+    pub desc: &'static str,
+}
+
+/// All the builtins, except `prev` and `prevN` which are handled by `get_builtin_name`
+pub static BUILTINS: &'static [Builtin] = &[
+    Builtin { names: &["ans"], val: AstVal::LastResult, usage: "ans",
+              desc: "the result of the previous calculation" },
+    Builtin { names: &["pi", "π"], val: AstVal::Const(Pi), usage: "pi",
+              desc: "the ratio of a circle's circumference to its diameter" },
+    Builtin { names: &["e"], val: AstVal::Const(E), usage: "e",
+              desc: "Euler's number, the base of the natural logarithm" },
+    Builtin { names: &["phi", "ϕ"], val: AstVal::Const(Phi), usage: "phi",
+              desc: "the golden ratio" },
+    Builtin { names: &["inf"], val: AstVal::Const(Inf), usage: "inf",
+              desc: "infinity, where -inf is negative infinity" },
+    Builtin { names: &["nan"], val: AstVal::Const(NaN), usage: "nan",
+              desc: "not a number, the result of e.g. 0/0" },
+    Builtin { names: &["sin"], val: AstVal::Func(Sin), usage: "sin(x)",
+              desc: "sine of the angle x" },
+    Builtin { names: &["cos"], val: AstVal::Func(Cos), usage: "cos(x)",
+              desc: "cosine of the angle x" },
+    Builtin { names: &["tan"], val: AstVal::Func(Tan), usage: "tan(x)",
+              desc: "tangent of the angle x" },
+    Builtin { names: &["sec"], val: AstVal::Func(Sec), usage: "sec(x)",
+              desc: "secant of the angle x, which is 1/cos(x)" },
+    Builtin { names: &["csc"], val: AstVal::Func(Csc), usage: "csc(x)",
+              desc: "cosecant of the angle x, which is 1/sin(x)" },
+    Builtin { names: &["cot"], val: AstVal::Func(Cot), usage: "cot(x)",
+              desc: "cotangent of the angle x, which is 1/tan(x)" },
+    Builtin { names: &["asin"], val: AstVal::Func(Asin), usage: "asin(x)",
+              desc: "the angle whose sine is x" },
+    Builtin { names: &["acos"], val: AstVal::Func(Acos), usage: "acos(x)",
+              desc: "the angle whose cosine is x" },
+    Builtin { names: &["atan"], val: AstVal::Func(Atan), usage: "atan(x)",
+              desc: "the angle whose tangent is x" },
+    Builtin { names: &["sqrt", "√"], val: AstVal::Func(Sqrt), usage: "sqrt(x)",
+              desc: "square root of x" },
+    Builtin { names: &["abs"], val: AstVal::Func(Abs), usage: "abs(x)",
+              desc: "absolute value of x, which can also be written |x|" },
+    Builtin { names: &["exp"], val: AstVal::Func(Exp), usage: "exp(x)",
+              desc: "e raised to the power x" },
+    Builtin { names: &["ln"], val: AstVal::Func(Ln), usage: "ln(x)",
+              desc: "natural logarithm of x" },
+    Builtin { names: &["log"], val: AstVal::Func(Log), usage: "log(x), log(x, b) or log_b(x)",
+              desc: "logarithm of x, in base 10 unless the base b is given, e.g. log_2(8) is 3" },
+    Builtin { names: &["frac"], val: AstVal::Func(Frac), usage: "frac(x)",
+              desc: "fractional part of x, with the same sign as x" },
+    Builtin { names: &["int"], val: AstVal::Func(IntPart), usage: "int(x)",
+              desc: "integer part of x, rounding towards zero" },
+    Builtin { names: &["floor"], val: AstVal::Func(Floor), usage: "floor(x) or ⌊x⌋",
+              desc: "x rounded down to a whole number" },
+    Builtin { names: &["ceil"], val: AstVal::Func(Ceil), usage: "ceil(x) or ⌈x⌉",
+              desc: "x rounded up to a whole number" },
+    Builtin { names: &["gamma"], val: AstVal::Func(Gamma), usage: "gamma(x)",
+              desc: "the gamma function, so gamma(n) is (n - 1)! for whole numbers n" },
+    Builtin { names: &["hypot"], val: AstVal::Func(Hypot), usage: "hypot(x, y)",
+              desc: "length of the hypotenuse of a right triangle with the sides x and y" },
+    Builtin { names: &["dist"], val: AstVal::Func(Dist), usage: "dist(x, y)",
+              desc: "distance between x and y, i.e. |x - y|" },
+    Builtin { names: &["snap"], val: AstVal::Func(Snap), usage: "snap(x, step)",
+              desc: "x rounded to the nearest multiple of step, e.g. snap(7, 5) is 5" },
+    Builtin { names: &["tobase"], val: AstVal::Func(ToBase), usage: "tobase(x, base)",
+              desc: "shows the whole number x in the given base, e.g. tobase(255, 16) is 0xff" },
+    Builtin { names: &["cf"], val: AstVal::Func(ContFrac), usage: "cf(x, n)",
+              desc: "shows the first n coefficients of the continued fraction of x, e.g. \
+                     cf(pi, 3) is [3; 7, 15]" },
+    Builtin { names: &["solve"], val: AstVal::Func(Solve), usage: "solve(lhs = rhs, x)",
+              desc: "solves a linear equation for x, e.g. solve(2*x + 3 = 7, x) is x = 2" },
+    Builtin { names: &["stddev"], val: AstVal::Func(Stddev), usage: "stddev(x, y, ...)",
+              desc: "population standard deviation of two or more values" },
+    Builtin { names: &["variance"], val: AstVal::Func(Variance), usage: "variance(x, y, ...)",
+              desc: "population variance of two or more values" },
+];
+
+/// Looks up the builtin called `name`, regardless of case
+pub fn find_builtin(name: &str) -> Option<&'static Builtin> {
+    let name = name.to_lowercase();
+    BUILTINS.iter().find(|builtin| builtin.names.contains(&name.as_ref()))
+}
+
+pub fn get_builtin_name(name: &String) -> Option<AstVal> {
+    // builtins are matched regardless of case, while variable names are case sensitive
+    let name = name.to_lowercase();
+    if name.starts_with("prev") {
+        let back = &name["prev".len()..];
+        if back.is_empty() {
+            return Some(AstVal::PrevResult(1));
+        } else if let Ok(n) = back.parse::<usize>() {
+            if n > 0 {
+                return Some(AstVal::PrevResult(n));
+            }
+        }
     }
+    find_builtin(&name).map(|builtin| builtin.val.clone())
+}
+
+/// Returns the base of a logarithm written with a subscript, e.g. 2 for `log_2`
+fn log_base(name: &str) -> Option<f64> {
+    let name = name.to_lowercase();
+    if !name.starts_with("log_") {
+        return None;
+    }
+    let base = &name["log_".len()..];
+    if !base.is_empty() && base.chars().all(|ch| ch.is_ascii_digit()) {
+        base.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Whether `name` means something to the parser, such that it can't name a variable or function
+pub fn is_reserved_name(name: &str) -> bool {
+    get_builtin_name(&name.to_string()).is_some() || log_base(name).is_some() ||
+    word_op(name).is_some()
+}
+
+/// Returns the operator written as the word `name`, i.e. `mod`, `div` or `of`
+fn word_op(name: &str) -> Option<AstOp> {
+    match name.to_lowercase().as_ref() {
+        "mod" => Some(AstOp::Mod),
+        "div" => Some(AstOp::IntDiv),
+        "of" => Some(AstOp::Of),
+        _ => None,
+    }
+}
+
+fn is_word_op(val: &TokVal) -> bool {
+    if let Name(ref name) = *val { word_op(name).is_some() } else { false }
+}
+
+pub fn is_comparison(val: &TokVal) -> bool {
+    if let Op(ref op) = *val {
+        let op: AstOp = op.clone().into();
+        op.is_comparison()
+    } else {
+        false
+    }
+}
+
+/// Checks that `target` is something which can be assigned to, which is only a variable
+fn check_assign_target(target: &Ast) -> CalcrResult<()> {
+    if let AstVal::Name(_) = target.val {
+        return Ok(());
+    }
+    let builtin = match target.val {
+        AstVal::Const(ref c) => Some(c.name().to_string()),
+        AstVal::LastResult => Some("ans".to_string()),
+        AstVal::PrevResult(n) => Some(format!("prev{}", n)),
+        _ => None,
+    };
+    if let Some(name) = builtin {
+        return Err(CalcrError {
+            desc: format!("`{}` is a builtin constant and cannot be reassigned", name),
+            span: Some(target.get_total_span()),
+        });
+    }
+    let assign_target = match *target {
+        Ast { val: AstVal::Func(_), span: _, branches: _ } => "function",
+        Ast { val: AstVal::Num(_), span: _, branches: _ } => "number",
+        _ => "equtation", // TODO: Make this case more nuanced
+    };
+    Err(CalcrError {
+        desc: format!("Cannot assign to {}", assign_target),
+        span: Some(target.get_total_span()),
+    })
 }
 
 pub struct Parser {
@@ -82,55 +331,105 @@ pub struct Parser {
     paren_level: u32,
     abs_level: u32,
     end_pos: usize,
+    depth: u32,
+    max_depth: u32,
+    functions: HashSet<String>,
 }
 
 impl Parser {
+    fn new(tokens: Vec<Token>, max_depth: u32) -> Parser {
+        let end_pos = tokens.last().and_then(|tok| Some(tok.span.1)).unwrap_or(0);
+        Parser {
+            iter: tokens.into_iter().peekable(),
+            paren_level: 0,
+            abs_level: 0,
+            end_pos: end_pos,
+            depth: 0,
+            max_depth: max_depth,
+            functions: HashSet::new(),
+        }
+    }
+
     fn parse_expression(&mut self) -> CalcrResult<Ast> {
         let eq = try!(self.parse_equation());
         if self.toks_empty() {
             Ok(eq)
         } else if self.next_tok_is(Op(TokOp::Assign)) {
             self.consume_tok();
-            if let AstVal::Name(_) = eq.val {
-                let rhs = try!(self.parse_equation());
-                Ok(Ast {
-                    val: AstVal::Op(AstOp::Assign),
-                    span: (eq.span.0, rhs.span.1),
-                    branches: vec!(eq, rhs)
-                })
-            } else {
-                let assign_target = match eq {
-                    Ast { val: AstVal::Func(_), span: _, branches: _ } => "function",
-                    Ast { val: AstVal::Const(_), span: _, branches: _ } => "constant",
-                    Ast { val: AstVal::Num(_), span: _, branches: _ } => "number",
-                    Ast { val: AstVal::LastResult, span: _, branches: _ } => "constant",
-                    _ => "equtation", // TODO: Make this case more nuanced
-                };
-                Err(CalcrError {
-                    desc: format!("Cannot assign to {}", assign_target),
-                    span: Some(eq.get_total_span()),
-                })
-            }
+            try!(check_assign_target(&eq));
+            let rhs = try!(self.parse_equation());
+            Ok(Ast {
+                val: AstVal::Op(AstOp::Assign),
+                span: (eq.span.0, rhs.span.1),
+                branches: vec!(eq, rhs)
+            })
+        } else if self.next_tok_is(Comma) && matches!(eq.val, AstVal::Name(_)) {
+            self.parse_multi_assign(eq)
         } else {
             let tok = self.consume_tok();
             Err(CalcrError {
-                desc: "Expected operator".to_string(),
+                desc: format!("Expected operator, found {}", tok.val.describe()),
                 span: Some(tok.span),
             })
         }
     }
 
-    fn parse_equation(&mut self) -> CalcrResult<Ast> {
-        let mut lhs = try!(self.parse_product());
-        while self.next_tok_matches(|val| *val == Op(TokOp::Plus) || *val == Op(TokOp::Minus)) {
-            let Token { val: tok_val, span: tok_span } = self.consume_tok();
-            let rhs = try!(self.parse_product());
-            lhs = Ast {
-                val: AstVal::Op(tok_val.op().unwrap().into()),
-                span: tok_span,
-                branches: vec!(lhs, rhs),
-            }
+    /// Parses the rest of an assignment to several variables, e.g. `a, b = 3, 4`, where `first`
+    /// is the first name
+    ///
+    /// The resulting `Assign` node has all the names as its first branches, followed by the same
+    /// number of values.
+    fn parse_multi_assign(&mut self, first: Ast) -> CalcrResult<Ast> {
+        let mut names = vec!(first);
+        while self.next_tok_is(Comma) {
+            self.consume_tok();
+            let name = try!(self.parse_equation());
+            try!(check_assign_target(&name));
+            names.push(name);
         }
+        if !self.next_tok_is(Op(TokOp::Assign)) {
+            let span = match self.iter.peek() {
+                Some(tok) => tok.span,
+                None => (self.end_pos, self.end_pos),
+            };
+            return Err(CalcrError {
+                desc: "Expected `=` after the names to assign".to_string(),
+                span: Some(span),
+            });
+        }
+        self.consume_tok();
+        let mut values = vec!(try!(self.parse_equation()));
+        while self.next_tok_is(Comma) {
+            self.consume_tok();
+            values.push(try!(self.parse_equation()));
+        }
+        let values_span = (values[0].get_total_span().0,
+                           values[values.len() - 1].get_total_span().1);
+        if values.len() != names.len() {
+            return Err(CalcrError {
+                desc: format!("Expected {} values to assign, found {}", names.len(),
+                              values.len()),
+                span: Some(values_span),
+            });
+        }
+        let span = (names[0].span.0, values_span.1);
+        names.extend(values);
+        Ok(Ast {
+            val: AstVal::Op(AstOp::Assign),
+            span: span,
+            branches: names,
+        })
+    }
+
+    // Each operator has its operands parsed by a separate function, so that the operator's frame
+    // is only on the stack while one of its operands is being parsed, and that nesting counts
+    // towards `max_depth` like delimiters do. This keeps the stack used by each level of nesting
+    // small, even in debug builds.
+
+    fn parse_equation(&mut self) -> CalcrResult<Ast> {
+        let lhs = try!(self.parse_conjunction());
+        let lhs = try!(self.parse_chain(lhs, |val| *val == Op(TokOp::Or),
+                                        Parser::parse_conjunction));
         if self.next_tok_matches(|val| val.is_close_delim()) && self.paren_level < 1 {
             let Token { val: _, span: tok_span } = self.consume_tok();
             Err(CalcrError {
@@ -148,54 +447,192 @@ impl Parser {
         }
     }
 
-    fn parse_product(&mut self) -> CalcrResult<Ast> {
-        let mut lhs = try!(self.parse_factor());
-        while self.next_tok_matches(|val| *val == Op(TokOp::Mult) || *val == Op(TokOp::Div)) {
+    fn parse_conjunction(&mut self) -> CalcrResult<Ast> {
+        let lhs = try!(self.parse_comparison());
+        self.parse_chain(lhs, |val| *val == Op(TokOp::And), Parser::parse_comparison)
+    }
+
+    fn parse_comparison(&mut self) -> CalcrResult<Ast> {
+        let lhs = try!(self.parse_exclusive_or());
+        if self.next_tok_matches(is_comparison) {
+            self.parse_comparison_rhs(lhs)
+        } else {
+            Ok(lhs)
+        }
+    }
+
+    /// Parses the comparison operator following `lhs` and its right hand side
+    fn parse_comparison_rhs(&mut self, lhs: Ast) -> CalcrResult<Ast> {
+        let Token { val: op, span: tok_span } = self.consume_tok();
+        try!(self.enter_nested(tok_span));
+        let rhs = try!(self.parse_exclusive_or());
+        self.depth -= 1;
+        if self.next_tok_matches(is_comparison) {
+            // `1 < x < 3` does not mean what it looks like, so it is not allowed
+            let tok = self.consume_tok();
+            return Err(CalcrError {
+                desc: "Comparisons cannot be chained".to_string(),
+                span: Some(tok.span),
+            });
+        }
+        let op = match op {
+            Op(op) => op.into(),
+            _ => return Err(CalcrError {
+                desc: "Expected a comparison operator".to_string(),
+                span: Some(tok_span),
+            }),
+        };
+        Ok(Ast {
+            val: AstVal::Op(op),
+            span: tok_span,
+            branches: vec!(lhs, rhs),
+        })
+    }
+
+    fn parse_exclusive_or(&mut self) -> CalcrResult<Ast> {
+        let lhs = try!(self.parse_sum());
+        self.parse_chain(lhs, |val| *val == Op(TokOp::Xor), Parser::parse_sum)
+    }
+
+    fn parse_sum(&mut self) -> CalcrResult<Ast> {
+        let lhs = try!(self.parse_product());
+        self.parse_chain(lhs, |val| *val == Op(TokOp::Plus) || *val == Op(TokOp::Minus),
+                         Parser::parse_product)
+    }
+
+    /// Parses the rest of a left-associative chain such as `a + b - c`, where `lhs` is the first
+    /// operand, `is_op` picks out the operators of the chain and `operand` parses the others
+    fn parse_chain<F, G>(&mut self, mut lhs: Ast, is_op: F, operand: G) -> CalcrResult<Ast>
+        where F: Fn(&TokVal) -> bool, G: Fn(&mut Parser) -> CalcrResult<Ast>
+    {
+        while self.next_tok_matches(&is_op) {
             let Token { val: tok_val, span: tok_span } = self.consume_tok();
-            let rhs = try!(self.parse_factor());
+            try!(self.enter_nested(tok_span));
+            let rhs = try!(operand(self));
+            self.depth -= 1;
             lhs = Ast {
                 val: AstVal::Op(tok_val.op().unwrap().into()),
                 span: tok_span,
                 branches: vec!(lhs, rhs),
-            };
+            }
         }
         Ok(lhs)
     }
 
+    fn parse_product(&mut self) -> CalcrResult<Ast> {
+        let lhs = try!(self.parse_factor());
+        self.parse_product_rest(lhs)
+    }
+
+    /// Parses any further factors multiplied onto `lhs`, explicitly as in `2*x` or implicitly as
+    /// in `2x`
+    fn parse_product_rest(&mut self, mut lhs: Ast) -> CalcrResult<Ast> {
+        loop {
+            if self.next_tok_matches(|val| *val == Op(TokOp::Mult) || *val == Op(TokOp::Div)) {
+                let Token { val: tok_val, span: tok_span } = self.consume_tok();
+                try!(self.enter_nested(tok_span));
+                let rhs = try!(self.parse_factor());
+                self.depth -= 1;
+                lhs = Ast {
+                    val: AstVal::Op(tok_val.op().unwrap().into()),
+                    span: tok_span,
+                    branches: vec!(lhs, rhs),
+                };
+            } else if self.next_tok_matches(is_word_op) {
+                let Token { val: tok_val, span: tok_span } = self.consume_tok();
+                try!(self.enter_nested(tok_span));
+                let rhs = try!(self.parse_factor());
+                self.depth -= 1;
+                let op = if let Name(ref name) = tok_val { word_op(name) } else { None };
+                lhs = Ast {
+                    val: AstVal::Op(op.unwrap()),
+                    span: tok_span,
+                    branches: vec!(lhs, rhs),
+                };
+            } else if self.next_tok_matches(|val| val.is_name() || val.is_open_delim()) {
+                // implicit multiplication - since there is no operator token, the span is the
+                // (possibly empty) gap between the two factors
+                let (opens_arguments, next_span) = match self.iter.peek() {
+                    Some(tok) => (tok.val.opens_arguments(), tok.span),
+                    None => return Ok(lhs),
+                };
+                // a name directly followed by a delimiter, e.g. `f(2)`, is in function position,
+                // so an unknown `f` can be reported as a function
+                let called = match lhs.val {
+                    AstVal::Name(ref name) if opens_arguments && next_span.0 == lhs.span.1 => {
+                        Some(name.clone())
+                    },
+                    _ => None,
+                };
+                if let Some(name) = called {
+                    lhs.val = AstVal::CalledName(name);
+                }
+                try!(self.enter_nested(next_span));
+                let rhs = try!(self.parse_factor());
+                self.depth -= 1;
+                lhs = Ast {
+                    val: AstVal::Op(AstOp::Mult),
+                    span: (lhs.get_total_span().1, rhs.get_total_span().0),
+                    branches: vec!(lhs, rhs),
+                };
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
     fn parse_factor(&mut self) -> CalcrResult<Ast> {
+        if self.next_tok_matches(|val| *val == Op(TokOp::Minus) || *val == Op(TokOp::Plus)) {
+            return self.parse_unary();
+        }
+        let lhs = try!(self.parse_exponent());
+        if self.next_tok_is(Op(TokOp::Pow)) {
+            self.parse_power(lhs)
+        } else {
+            Ok(lhs)
+        }
+    }
+
+    /// Parses a factor with a unary `-` or `+` in front of it
+    fn parse_unary(&mut self) -> CalcrResult<Ast> {
         // when we lex we only store `Minus`s since we do not have any context there,
         // however we know if we see a `Minus` now, then it is a `Neg`.
-        if self.next_tok_is(Op(TokOp::Minus)) {
-            let tok_span = self.consume_tok().span;
-            let rhs = try!(self.parse_factor());
+        let Token { val: tok_val, span: tok_span } = self.consume_tok();
+        try!(self.enter_nested(tok_span));
+        let rhs = try!(self.parse_factor());
+        self.depth -= 1;
+        if tok_val == Op(TokOp::Minus) {
             Ok(Ast {
                 val: AstVal::Op(AstOp::Neg),
                 span: tok_span,
                 branches: vec!(rhs),
             })
         } else {
-            let lhs = try!(self.parse_exponent());
-            if self.next_tok_is(Op(TokOp::Pow)) {
-                let tok_span = self.consume_tok().span;
-                let rhs = try!(self.parse_factor());
-                Ok(Ast {
-                    val: AstVal::Op(AstOp::Pow),
-                    span: tok_span,
-                    branches: vec!(lhs, rhs),
-                })
-            } else {
-                Ok(lhs)
-            }
+            // a unary plus does nothing, so there is no node for it
+            Ok(rhs)
         }
     }
 
+    /// Parses the `^` following `lhs` and its exponent
+    fn parse_power(&mut self, lhs: Ast) -> CalcrResult<Ast> {
+        let tok_span = self.consume_tok().span;
+        try!(self.enter_nested(tok_span));
+        let rhs = try!(self.parse_factor());
+        self.depth -= 1;
+        Ok(Ast {
+            val: AstVal::Op(AstOp::Pow),
+            span: tok_span,
+            branches: vec!(lhs, rhs),
+        })
+    }
+
     fn parse_exponent(&mut self) -> CalcrResult<Ast> {
         let mut out = try!(self.parse_number());
 
-        while self.next_tok_is(Op(TokOp::Fact)) {
-            let tok_span = self.consume_tok().span;
+        while self.next_tok_matches(|val| *val == Op(TokOp::Fact) || *val == Op(TokOp::Deg)) {
+            let Token { val: tok_val, span: tok_span } = self.consume_tok();
             out = Ast {
-                val: AstVal::Op(AstOp::Fact),
+                val: AstVal::Op(tok_val.op().unwrap().into()),
                 span: tok_span,
                 branches: vec!(out),
             };
@@ -212,68 +649,12 @@ impl Parser {
         } else {
             let Token { val: tok_val, span: tok_span } = self.consume_tok();
             match tok_val {
-                Name(ref name) => {
-                    let val = match get_builtin_name(name) {
-                        Some(val) => val,
-                        None => AstVal::Name(name.clone()),
-                    };
-                    if let AstVal::Func(_) = val {
-                        // it's a function so we need to grab its argument
-                        if self.next_tok_matches(|val| val.is_open_delim()) {
-                            // since we know the next token is an open paren, we use
-                            // this function to get its AST
-                            let arg = try!(self.parse_number());
-                            Ok(Ast {
-                                val: val,
-                                span: tok_span,
-                                branches: vec!(arg) ,
-                            })
-                        } else {
-                            Err(CalcrError {
-                                desc: "Missing opening delimiter after function".to_string(),
-                                span: Some(tok_span),
-                            })
-                        }
-                    } else {
-                        Ok(Ast {
-                            val: val,
-                            span: tok_span,
-                            branches: vec!(),
-                        })
-                    }
-                },
-                OpenDelim(kind) => {
-                    self.paren_level += 1;
-                    let eq = try!(self.parse_equation());
-                    if !self.next_tok_is(CloseDelim(kind)) {
-                        Err(CalcrError {
-                            desc: "Missing matching closing delimiter".to_string(),
-                            span: Some(tok_span),
-                        })
-                    } else {
-                        self.consume_tok();
-                        self.paren_level -= 1;
-                        Ok(eq)
-                    }
-                },
-                AbsDelim => {
-                    self.abs_level += 1;
-                    let eq = try!(self.parse_equation());
-                    if !self.next_tok_is(AbsDelim) {
-                        Err(CalcrError {
-                            desc: "Missing closing abs delimiter".to_string(),
-                            span: Some(tok_span),
-                        })
-                    } else {
-                        self.abs_level -= 1;
-                        let close_delim_span = self.consume_tok().span;
-                        Ok(Ast {
-                            val: AstVal::Func(Abs),
-                            span: (tok_span.0, close_delim_span.1),
-                            branches: vec!(eq),
-                        })
-                    }
-                },
+                Name(ref name) if word_op(name).is_some() => Err(CalcrError {
+                    desc: format!("Expected number or constant, found operator `{}`", name),
+                    span: Some(tok_span),
+                }),
+                Name(ref name) => self.parse_name(name, tok_span),
+                OpenDelim(_) | AbsDelim => self.parse_delimited(tok_val, tok_span),
                 Num(num) => {
                     Ok(Ast {
                         val: AstVal::Num(num),
@@ -289,6 +670,177 @@ impl Parser {
         }
     }
 
+    /// Parses the name `name`, which has just been consumed, along with its arguments if it is a
+    /// function
+    fn parse_name(&mut self, name: &String, tok_span: Span) -> CalcrResult<Ast> {
+        let base = log_base(name);
+        let val = match get_builtin_name(name) {
+            Some(val) => val,
+            None if base.is_some() => AstVal::Func(Log),
+            None if self.functions.contains(name) => AstVal::Call(name.clone()),
+            None => AstVal::Name(name.clone()),
+        };
+        if let AstVal::Func(_) | AstVal::Call(_) = val {
+            // it's a function so we need to grab its argument
+            let mut args = if self.next_tok_matches(|val| val.opens_arguments()) {
+                try!(self.parse_arguments(val == AstVal::Func(Solve)))
+            } else if self.next_tok_matches(|val| val.is_name() || val.is_num()) {
+                // a single atomic argument without delimiters, e.g. `sin pi`
+                try!(self.enter_nested(tok_span));
+                let arg = try!(self.parse_number());
+                self.depth -= 1;
+                vec!(arg)
+            } else {
+                return Err(self.missing_argument(name));
+            };
+            if let Some(base) = base {
+                if args.len() != 1 {
+                    return Err(CalcrError {
+                        desc: "Function takes exactly one argument".to_string(),
+                        span: Some((tok_span.0, args[args.len() - 1].get_total_span().1)),
+                    });
+                }
+                // `log_2(x)` is the same as `log(x, 2)`, where the base is spanned by the digits
+                // after `log_`
+                args.push(Ast {
+                    val: AstVal::Num(base),
+                    span: (tok_span.0 + "log_".len(), tok_span.1),
+                    branches: vec!(),
+                });
+            }
+            Ok(Ast {
+                val: val,
+                span: tok_span,
+                branches: args,
+            })
+        } else {
+            Ok(Ast {
+                val: val,
+                span: tok_span,
+                branches: vec!(),
+            })
+        }
+    }
+
+    /// Gives the error for the function `name` not being followed by an argument
+    fn missing_argument(&mut self, name: &str) -> CalcrError {
+        let (found, span) = match self.iter.peek() {
+            Some(tok) => (tok.val.describe(), tok.span),
+            None => ("nothing".to_string(), (self.end_pos, self.end_pos)),
+        };
+        CalcrError {
+            desc: format!("{} expects a parenthesized argument, found {}", name, found),
+            span: Some(span),
+        }
+    }
+
+    /// Parses the rest of a delimited expression such as `(1+2)` or `|x|`, where `open` is the
+    /// opening delimiter, which has just been consumed
+    fn parse_delimited(&mut self, open: TokVal, tok_span: Span) -> CalcrResult<Ast> {
+        try!(self.enter_nested(tok_span));
+        let (close, func, missing) = match open {
+            AbsDelim => {
+                self.abs_level += 1;
+                (AbsDelim, Some(Abs), "Missing closing abs delimiter")
+            },
+            OpenDelim(kind) => {
+                self.paren_level += 1;
+                let func = match kind {
+                    DelimKind::Floor => Some(Floor),
+                    DelimKind::Ceil => Some(Ceil),
+                    _ => None,
+                };
+                (CloseDelim(kind), func, "Missing matching closing delimiter")
+            },
+            _ => return Err(CalcrError {
+                desc: "Internal error - expected an opening delimiter".to_string(),
+                span: Some(tok_span),
+            }),
+        };
+        let eq = try!(self.parse_equation());
+        if !self.next_tok_is(close) {
+            return Err(CalcrError {
+                desc: missing.to_string(),
+                span: Some(tok_span),
+            });
+        }
+        let close_delim_span = self.consume_tok().span;
+        if func == Some(Abs) {
+            self.abs_level -= 1;
+        } else {
+            self.paren_level -= 1;
+        }
+        self.depth -= 1;
+        match func {
+            Some(func) => Ok(Ast {
+                val: AstVal::Func(func),
+                span: (tok_span.0, close_delim_span.1),
+                branches: vec!(eq),
+            }),
+            None => Ok(eq),
+        }
+    }
+
+    /// Parses a delimited, comma separated list of function arguments
+    ///
+    /// If `equation` is true, the first argument may be an equation such as `2x + 3 = 7`, which
+    /// is then an `Assign` node.
+    fn parse_arguments(&mut self, equation: bool) -> CalcrResult<Vec<Ast>> {
+        let Token { val: tok_val, span: tok_span } = self.consume_tok();
+        let kind = if let OpenDelim(kind) = tok_val {
+            kind
+        } else {
+            return Err(CalcrError {
+                desc: "Internal error - expected opening delimiter before arguments".to_string(),
+                span: Some(tok_span),
+            });
+        };
+        try!(self.enter_nested(tok_span));
+        self.paren_level += 1;
+        let mut first = try!(self.parse_equation());
+        if equation && self.next_tok_is(Op(TokOp::Assign)) {
+            self.consume_tok();
+            let rhs = try!(self.parse_equation());
+            first = Ast {
+                val: AstVal::Op(AstOp::Assign),
+                span: (first.span.0, rhs.span.1),
+                branches: vec!(first, rhs),
+            };
+        }
+        let mut args = vec!(first);
+        while self.next_tok_is(Comma) {
+            self.consume_tok();
+            args.push(try!(self.parse_equation()));
+        }
+        if !self.next_tok_is(CloseDelim(kind)) {
+            Err(CalcrError {
+                desc: "Missing matching closing delimiter".to_string(),
+                span: Some(tok_span),
+            })
+        } else {
+            self.consume_tok();
+            self.paren_level -= 1;
+            self.depth -= 1;
+            Ok(args)
+        }
+    }
+
+    /// Goes one level deeper into a nested expression
+    ///
+    /// Fails if this takes us past `max_depth`, since recursing any further could overflow the
+    /// stack. The caller must decrement `depth` again once it is done with the nested expression.
+    fn enter_nested(&mut self, span: (usize, usize)) -> CalcrResult<()> {
+        if self.depth >= self.max_depth {
+            Err(CalcrError {
+                desc: "Expression too deeply nested".to_string(),
+                span: Some(span),
+            })
+        } else {
+            self.depth += 1;
+            Ok(())
+        }
+    }
+
     /// Peeks at the next token and check whether its values is equal to `val`
     fn next_tok_is(&mut self, val: TokVal) -> bool {
         self.next_tok_matches(|v| *v == val)
@@ -319,34 +871,325 @@ mod tests {
     use super::*;
     use token::Token;
     use token::TokVal;
+    use token::DelimKind;
     use ast::Ast;
     use ast::AstVal;
-    use ast::AstBranch;
     use ast::ConstKind::*;
+    use ast::FuncKind::*;
+    use ast::OpKind as AstOp;
+    use token::OpKind as TokOp;
 
     #[test]
     fn single_num() {
         let toks = vec!(Token { val: TokVal::Num(2.0), span: (0, 1) });
         let ast = parse_tokens(toks);
-        assert_eq!(ast, Ok(Ast { val: AstVal::Num(2.0), span: (0, 1), branches: AstBranch::Leaf }));
+        assert_eq!(ast, Ok(Ast { val: AstVal::Num(2.0), span: (0, 1), branches: vec!() }));
     }
 
     #[test]
     fn constants() {
         assert_eq!(parse_tokens(vec!(Token { val: TokVal::Name("pi".to_string()), span: (0, 2)})),
-                   Ok(Ast { val: AstVal::Const(Pi), span: (0, 2), branches: AstBranch::Leaf }));
+                   Ok(Ast { val: AstVal::Const(Pi), span: (0, 2), branches: vec!() }));
 
         assert_eq!(parse_tokens(vec!(Token { val: TokVal::Name("π".to_string()), span: (0, 1)})),
-                   Ok(Ast { val: AstVal::Const(Pi), span: (0, 1), branches: AstBranch::Leaf }));
+                   Ok(Ast { val: AstVal::Const(Pi), span: (0, 1), branches: vec!() }));
 
         assert_eq!(parse_tokens(vec!(Token { val: TokVal::Name("e".to_string()), span: (0, 1)})),
-                   Ok(Ast { val: AstVal::Const(E), span: (0, 1), branches: AstBranch::Leaf }));
+                   Ok(Ast { val: AstVal::Const(E), span: (0, 1), branches: vec!() }));
 
         assert_eq!(parse_tokens(vec!(Token { val: TokVal::Name("phi".to_string()), span: (0, 3)})),
-                   Ok(Ast { val: AstVal::Const(Phi), span: (0, 3), branches: AstBranch::Leaf }));
+                   Ok(Ast { val: AstVal::Const(Phi), span: (0, 3), branches: vec!() }));
 
         assert_eq!(parse_tokens(vec!(Token { val: TokVal::Name("ϕ".to_string()), span: (0, 1)})),
-                   Ok(Ast { val: AstVal::Const(Phi), span: (0, 1), branches: AstBranch::Leaf }));
+                   Ok(Ast { val: AstVal::Const(Phi), span: (0, 1), branches: vec!() }));
+    }
+
+    #[test]
+    fn function_arguments() {
+        let toks = vec!(Token { val: TokVal::Name("stddev".to_string()), span: (0, 6) },
+                        Token { val: TokVal::OpenDelim(DelimKind::Paren), span: (6, 7) },
+                        Token { val: TokVal::Num(1.0), span: (7, 8) },
+                        Token { val: TokVal::Comma, span: (8, 9) },
+                        Token { val: TokVal::Num(2.0), span: (9, 10) },
+                        Token { val: TokVal::CloseDelim(DelimKind::Paren), span: (10, 11) });
+        assert_eq!(parse_tokens(toks),
+                   Ok(Ast {
+                       val: AstVal::Func(Stddev),
+                       span: (0, 6),
+                       branches: vec!(Ast { val: AstVal::Num(1.0), span: (7, 8), branches: vec!() },
+                                      Ast { val: AstVal::Num(2.0), span: (9, 10), branches: vec!() }),
+                   }));
+    }
+
+    #[test]
+    fn implicit_mult() {
+        let toks = vec!(Token { val: TokVal::Num(2.0), span: (0, 1) },
+                        Token { val: TokVal::Name("e".to_string()), span: (2, 3) });
+        assert_eq!(parse_tokens(toks),
+                   Ok(Ast {
+                       val: AstVal::Op(AstOp::Mult),
+                       span: (1, 2),
+                       branches: vec!(Ast { val: AstVal::Num(2.0), span: (0, 1), branches: vec!() },
+                                      Ast { val: AstVal::Const(E), span: (2, 3), branches: vec!() }),
+                   }));
+    }
+
+    #[test]
+    fn name_in_function_position() {
+        let ast = parse_tokens(lex_equation(&"f(2)".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.val, AstVal::Op(AstOp::Mult));
+        assert_eq!(ast.branches[0].val, AstVal::CalledName("f".to_string()));
+        assert_eq!(ast.to_string(), "f * 2");
+        let ast = parse_tokens(lex_equation(&"f (2)".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.branches[0].val, AstVal::Name("f".to_string()));
+    }
+
+    #[test]
+    fn no_implicit_mult_between_numbers() {
+        let toks = vec!(Token { val: TokVal::Num(2.0), span: (0, 1) },
+                        Token { val: TokVal::Num(3.0), span: (2, 3) });
+        assert!(parse_tokens(toks).is_err());
+    }
+
+    #[test]
+    fn classify() {
+        use token::TokenCategory::*;
+        assert_eq!(classify_tokens("2 + sin(x) * pi"),
+                   Ok(vec!(((0, 1), Number),
+                           ((2, 3), Operator),
+                           ((4, 7), Function),
+                           ((7, 8), Delimiter),
+                           ((8, 9), Name),
+                           ((9, 10), Delimiter),
+                           ((11, 12), Operator),
+                           ((13, 15), Constant))));
+    }
+
+    #[test]
+    fn log_subscript() {
+        let ast = parse_tokens(lex_equation(&"log_2(8)".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.val, AstVal::Func(Log));
+        assert_eq!(ast.span, (0, 5));
+        assert_eq!(ast.branches[0].val, AstVal::Num(8.0));
+        assert_eq!(ast.branches[1].val, AstVal::Num(2.0));
+        assert_eq!(ast.branches[1].span, (4, 5));
+        assert_eq!(ast.to_string(), "log_2(8)");
+        let ast = parse_tokens(lex_equation(&"log(8, 2.5)".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.to_string(), "log(8, 2.5)");
+        // only digits make a subscript, so anything else is an ordinary name
+        let ast = parse_tokens(lex_equation(&"log_x".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.val, AstVal::Name("log_x".to_string()));
+    }
+
+    #[test]
+    fn undelimited_function_arg() {
+        let toks = vec!(Token { val: TokVal::Name("sin".to_string()), span: (0, 3) },
+                        Token { val: TokVal::Name("pi".to_string()), span: (4, 6) });
+        assert_eq!(parse_tokens(toks),
+                   Ok(Ast {
+                       val: AstVal::Func(Sin),
+                       span: (0, 3),
+                       branches: vec!(Ast { val: AstVal::Const(Pi), span: (4, 6), branches: vec!() }),
+                   }));
+    }
+
+    #[test]
+    fn undelimited_function_arg_precedence() {
+        let sin_two = || Ast {
+            val: AstVal::Func(Sin),
+            span: (0, 3),
+            branches: vec!(Ast { val: AstVal::Num(2.0), span: (4, 5), branches: vec!() }),
+        };
+
+        // sin 2 + 3
+        let toks = vec!(Token { val: TokVal::Name("sin".to_string()), span: (0, 3) },
+                        Token { val: TokVal::Num(2.0), span: (4, 5) },
+                        Token { val: TokVal::Op(TokOp::Plus), span: (6, 7) },
+                        Token { val: TokVal::Num(3.0), span: (8, 9) });
+        assert_eq!(parse_tokens(toks),
+                   Ok(Ast {
+                       val: AstVal::Op(AstOp::Plus),
+                       span: (6, 7),
+                       branches: vec!(sin_two(),
+                                      Ast { val: AstVal::Num(3.0), span: (8, 9), branches: vec!() }),
+                   }));
+
+        // sin 2 x
+        let toks = vec!(Token { val: TokVal::Name("sin".to_string()), span: (0, 3) },
+                        Token { val: TokVal::Num(2.0), span: (4, 5) },
+                        Token { val: TokVal::Name("x".to_string()), span: (6, 7) });
+        assert_eq!(parse_tokens(toks),
+                   Ok(Ast {
+                       val: AstVal::Op(AstOp::Mult),
+                       span: (5, 6),
+                       branches: vec!(sin_two(),
+                                      Ast {
+                                          val: AstVal::Name("x".to_string()),
+                                          span: (6, 7),
+                                          branches: vec!(),
+                                      }),
+                   }));
+    }
+
+    #[test]
+    fn function_without_arg() {
+        let toks = vec!(Token { val: TokVal::Name("sin".to_string()), span: (0, 3) },
+                        Token { val: TokVal::Op(TokOp::Minus), span: (4, 5) },
+                        Token { val: TokVal::Num(2.0), span: (5, 6) });
+        assert!(parse_tokens(toks).is_err());
+    }
+
+    #[test]
+    fn assign_to_builtin() {
+        let toks = lex_equation(&"e = 2.7".to_string()).unwrap();
+        let err = parse_tokens(toks).unwrap_err();
+        assert_eq!(err.desc, "`e` is a builtin constant and cannot be reassigned");
+        assert_eq!(err.span, Some((0, 1)));
+        let toks = lex_equation(&"ans = 2".to_string()).unwrap();
+        let err = parse_tokens(toks).unwrap_err();
+        assert_eq!(err.desc, "`ans` is a builtin constant and cannot be reassigned");
+    }
+
+    #[test]
+    fn multi_assign() {
+        let toks = lex_equation(&"a, b = 3, 4 + 1".to_string()).unwrap();
+        let ast = parse_tokens(toks).unwrap();
+        assert_eq!(ast.val, AstVal::Op(AstOp::Assign));
+        assert_eq!(ast.branches.len(), 4);
+        assert_eq!(ast.to_string(), "a, b = 3, 4 + 1");
+        let toks = lex_equation(&"a, b = 1".to_string()).unwrap();
+        let err = parse_tokens(toks).unwrap_err();
+        assert_eq!(err.desc, "Expected 2 values to assign, found 1");
+        assert_eq!(err.span, Some((7, 8)));
+        let toks = lex_equation(&"a, b = 1, 2, 3".to_string()).unwrap();
+        assert_eq!(parse_tokens(toks).unwrap_err().span, Some((7, 14)));
+        let toks = lex_equation(&"a, pi = 1, 2".to_string()).unwrap();
+        let err = parse_tokens(toks).unwrap_err();
+        assert_eq!(err.desc, "`pi` is a builtin constant and cannot be reassigned");
+        let toks = lex_equation(&"a, b".to_string()).unwrap();
+        assert_eq!(parse_tokens(toks).unwrap_err().span, Some((4, 4)));
+    }
+
+    #[test]
+    fn comparison() {
+        let toks = lex_equation(&"1 + 2 < 4 && 3 == 3".to_string()).unwrap();
+        let ast = parse_tokens(toks).unwrap();
+        assert_eq!(ast.to_string(), "1 + 2 < 4 && 3 == 3");
+        assert_eq!(ast.branches[0].val, AstVal::Op(AstOp::Less));
+        let toks = lex_equation(&"1 < 2 <= 3".to_string()).unwrap();
+        let err = parse_tokens(toks).unwrap_err();
+        assert_eq!(err.desc, "Comparisons cannot be chained");
+        assert_eq!(err.span, Some((6, 8)));
+    }
+
+    #[test]
+    fn rounding_delims() {
+        let ast = parse_tokens(lex_equation(&"⌊3.7⌋".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.val, AstVal::Func(Floor));
+        assert_eq!(ast.span, (0, 5));
+        assert_eq!(ast.branches[0].val, AstVal::Num(3.7));
+        let ast = parse_tokens(lex_equation(&"2⌈(1 + ⌊x⌋)⌉".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.to_string(), "2 * ceil(1 + floor(x))");
+        let err = parse_tokens(lex_equation(&"1 + ⌊3.7⌉".to_string()).unwrap()).unwrap_err();
+        assert_eq!(err.desc, "Missing matching closing delimiter");
+        assert_eq!(err.span, Some((4, 5)));
+        let err = parse_tokens(lex_equation(&"⌈(3.7⌉)".to_string()).unwrap()).unwrap_err();
+        assert_eq!(err.span, Some((1, 2)));
+    }
+
+    #[test]
+    fn factorial_binding() {
+        for &(eq, shown) in [("(2+3)!", "(2 + 3)!"),
+                             ("2+3!", "2 + 3!"),
+                             ("3!!", "(3!)!"),
+                             ("-3!", "-3!")].iter() {
+            let ast = parse_tokens(lex_equation(&eq.to_string()).unwrap()).unwrap();
+            assert_eq!(ast.to_string(), shown);
+        }
+        // the factorial only applies to the 3
+        let ast = parse_tokens(lex_equation(&"2+3!".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.val, AstVal::Op(AstOp::Plus));
+        assert_eq!(ast.branches[1].val, AstVal::Op(AstOp::Fact));
+        // while a double factorial is an iterated one
+        let ast = parse_tokens(lex_equation(&"3!!".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.branches[0].val, AstVal::Op(AstOp::Fact));
+    }
+
+    #[test]
+    fn word_operators() {
+        let ast = parse_tokens(lex_equation(&"7 mod 3".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.val, AstVal::Op(AstOp::Mod));
+        assert_eq!(ast.span, (2, 5));
+        // they bind like multiplication and division
+        let ast = parse_tokens(lex_equation(&"1 + 7 DIV 2 * 3".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.to_string(), "1 + 7 div 2 * 3");
+        assert_eq!(ast.branches[1].branches[0].val, AstVal::Op(AstOp::IntDiv));
+        let err = parse_tokens(lex_equation(&"mod = 3".to_string()).unwrap()).unwrap_err();
+        assert_eq!(err.span, Some((0, 3)));
+        let ast = parse_tokens(lex_equation(&"0.2 of 50 + 1".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.branches[0].val, AstVal::Op(AstOp::Of));
+        assert_eq!(ast.branches[0].span, (4, 6));
+        assert_eq!(ast.to_string(), "0.2 of 50 + 1");
+    }
+
+    #[test]
+    fn unary_plus() {
+        let ast = parse_tokens(lex_equation(&"+5".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.val, AstVal::Num(5.0));
+        assert_eq!(ast.span, (1, 2));
+        let ast = parse_tokens(lex_equation(&"3 - +5".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.to_string(), "3 - 5");
+        let ast = parse_tokens(lex_equation(&"- +5".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.to_string(), "-5");
+        assert!(parse_tokens(lex_equation(&"3 +".to_string()).unwrap()).is_err());
+    }
+
+    #[test]
+    fn negative_exponent() {
+        // the sign belongs to the exponent, with or without parentheses
+        for eq in ["2^-0.5", "2^(-0.5)"].iter() {
+            let ast = parse_tokens(lex_equation(&eq.to_string()).unwrap()).unwrap();
+            assert_eq!(ast.val, AstVal::Op(AstOp::Pow));
+            assert_eq!(ast.branches[0].val, AstVal::Num(2.0));
+            assert_eq!(ast.branches[1].val, AstVal::Op(AstOp::Neg));
+            assert_eq!(ast.branches[1].branches[0].val, AstVal::Num(0.5));
+        }
+        // while a leading minus applies to the whole power
+        let ast = parse_tokens(lex_equation(&"-2^2".to_string()).unwrap()).unwrap();
+        assert_eq!(ast.val, AstVal::Op(AstOp::Neg));
+        assert_eq!(ast.branches[0].val, AstVal::Op(AstOp::Pow));
+    }
+
+    #[test]
+    fn solve_equation() {
+        let toks = lex_equation(&"solve(2x = 4, x)".to_string()).unwrap();
+        let ast = parse_tokens(toks).unwrap();
+        assert_eq!(ast.branches[0].val, AstVal::Op(AstOp::Assign));
+        assert_eq!(ast.to_string(), "solve(2 * x = 4, x)");
+        let toks = lex_equation(&"hypot(x = 4, x)".to_string()).unwrap();
+        assert!(parse_tokens(toks).is_err());
+    }
+
+    #[test]
+    fn malformed_function_call() {
+        for &(eq, desc, span) in [("sqrt)", "sqrt expects a parenthesized argument, found `)`",
+                                   (4, 5)),
+                                  ("sin + 1", "sin expects a parenthesized argument, found \
+                                               operator `+`", (4, 5)),
+                                  ("2 * log", "log expects a parenthesized argument, found \
+                                               nothing", (7, 7)),
+                                  ("ln, 2", "ln expects a parenthesized argument, found `,`",
+                                   (2, 3))].iter() {
+            let toks = lex_equation(&eq.to_string()).unwrap();
+            let err = parse_tokens(toks).unwrap_err();
+            assert_eq!(err.desc, desc);
+            assert_eq!(err.span, Some(span));
+        }
+        // the argument is only the 8, so the comma is unexpected
+        let toks = lex_equation(&"log 8, 2".to_string()).unwrap();
+        let err = parse_tokens(toks).unwrap_err();
+        assert_eq!(err.desc, "Expected operator, found `,`");
+        assert_eq!(err.span, Some((5, 6)));
     }
 
     #[test]
@@ -0,0 +1,155 @@
+//! Reverse Polish notation, where operators follow their operands, e.g. `3 4 +` is 7
+//!
+//! Numbers, constants and variables are pushed onto a stack, while operators and functions pop
+//! their operands off the stack and push the result back. Instead of computing the results right
+//! away, the stack holds `Ast`s, so the expression can be evaluated by the usual interpreter.
+
+use ast::{Ast, AstVal, FuncKind};
+use token::{Token, TokVal};
+use token::OpKind as TokOp;
+use parser::get_builtin_name;
+use errors::{CalcrResult, CalcrError};
+
+/// Turns the tokens of an expression in reverse Polish notation into an `Ast`
+pub fn parse_rpn(toks: Vec<Token>) -> CalcrResult<Ast> {
+    let mut stack: Vec<Ast> = Vec::new();
+    let mut iter = toks.into_iter().peekable();
+    while let Some(Token { val, span }) = iter.next() {
+        let (val, arity) = match val {
+            TokVal::Num(num) => (AstVal::Num(num), 0),
+            // a minus directly in front of a number is part of the number, e.g. `-3`
+            TokVal::Op(TokOp::Minus) if iter.peek().is_some_and(|tok| {
+                tok.val.is_num() && tok.span.0 == span.1
+            }) => {
+                let (num, num_span) = match iter.next() {
+                    Some(Token { val: TokVal::Num(num), span: num_span }) => (num, num_span),
+                    _ => return Err(CalcrError {
+                        desc: "Expected a number after `-`".to_string(),
+                        span: Some(span),
+                    }),
+                };
+                stack.push(Ast {
+                    val: AstVal::Num(-num),
+                    span: (span.0, num_span.1),
+                    branches: vec!(),
+                });
+                continue;
+            },
+            TokVal::Op(TokOp::Assign) => return Err(CalcrError {
+                desc: "Assignment is not supported in RPN mode".to_string(),
+                span: Some(span),
+            }),
+            TokVal::Op(op) => {
+                let arity = match op {
+                    TokOp::Fact | TokOp::Deg => 1,
+                    _ => 2,
+                };
+                (AstVal::Op(op.into()), arity)
+            },
+            TokVal::Name(name) => match get_builtin_name(&name) {
+                // these take any number of arguments, so they use up the whole stack
+                Some(AstVal::Func(FuncKind::Stddev)) => (AstVal::Func(FuncKind::Stddev),
+                                                         stack.len()),
+                Some(AstVal::Func(FuncKind::Variance)) => (AstVal::Func(FuncKind::Variance),
+                                                           stack.len()),
+                Some(val @ AstVal::Func(FuncKind::Hypot)) |
+                Some(val @ AstVal::Func(FuncKind::Dist)) |
+                Some(val @ AstVal::Func(FuncKind::Snap)) |
+                Some(val @ AstVal::Func(FuncKind::ToBase)) |
+                Some(val @ AstVal::Func(FuncKind::ContFrac)) |
+                Some(val @ AstVal::Func(FuncKind::Solve)) => (val, 2),
+                Some(val @ AstVal::Func(_)) => (val, 1),
+                Some(val) => (val, 0),
+                None => (AstVal::Name(name), 0),
+            },
+            _ => return Err(CalcrError {
+                desc: "Delimiters are not supported in RPN mode".to_string(),
+                span: Some(span),
+            }),
+        };
+        if stack.len() < arity || (arity == 0 && is_func(&val)) {
+            return Err(CalcrError {
+                desc: format!("Not enough operands for {}", symbol(&val)),
+                span: Some(span),
+            });
+        }
+        let split = stack.len() - arity;
+        let branches = stack.split_off(split);
+        stack.push(Ast {
+            val: val,
+            span: span,
+            branches: branches,
+        });
+    }
+    match stack.len() {
+        0 => Err(CalcrError {
+            desc: "Expected number or constant".to_string(),
+            span: None,
+        }),
+        1 => Ok(stack.pop().unwrap()),
+        _ => Err(CalcrError {
+            desc: "Too many operands, expected a single result".to_string(),
+            span: Some(stack[1].get_total_span()),
+        }),
+    }
+}
+
+fn is_func(val: &AstVal) -> bool {
+    matches!(*val, AstVal::Func(_))
+}
+
+/// Returns how `val` is written, for use in error messages
+fn symbol(val: &AstVal) -> &'static str {
+    match *val {
+        AstVal::Op(ref op) => op.symbol(),
+        AstVal::Func(ref func) => func.name(),
+        _ => "value",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lexer::lex_equation;
+    use interpreter::Interpreter;
+    use super::parse_rpn;
+
+    fn eval(expr: &str) -> Result<f64, String> {
+        let toks = try!(lex_equation(&expr.to_string()).map_err(|e| e.desc));
+        let ast = try!(parse_rpn(toks).map_err(|e| e.desc));
+        Interpreter::new().eval_ast(&ast).map(|res| res.unwrap().value()).map_err(|e| e.desc)
+    }
+
+    #[test]
+    fn basic() {
+        assert_eq!(eval("3 4 +"), Ok(7.0));
+        assert_eq!(eval("3 4 -"), Ok(-1.0));
+        assert_eq!(eval("2 3 4 * +"), Ok(14.0));
+        assert_eq!(eval("-2 3 ^"), Ok(-8.0));
+        assert_eq!(eval("3 ! 2 /"), Ok(3.0));
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(eval("16 sqrt"), Ok(4.0));
+        assert_eq!(eval("pi 2 / sin"), Ok(1.0));
+        assert_eq!(eval("1 2 3 4 variance"), Ok(1.25));
+        assert_eq!(eval("3 4 hypot"), Ok(5.0));
+        assert_eq!(eval("3 7 dist"), Ok(4.0));
+    }
+
+    #[test]
+    fn underflow() {
+        let toks = lex_equation(&"3 + 4".to_string()).unwrap();
+        let err = parse_rpn(toks).unwrap_err();
+        assert_eq!(err.desc, "Not enough operands for +");
+        assert_eq!(err.span, Some((2, 3)));
+        assert_eq!(eval("sqrt"), Err("Not enough operands for sqrt".to_string()));
+    }
+
+    #[test]
+    fn too_many_operands() {
+        assert_eq!(eval("1 2 3 +"),
+                   Err("Too many operands, expected a single result".to_string()));
+        assert!(eval("(1 2 +)").is_err());
+    }
+}
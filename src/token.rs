@@ -1,11 +1,26 @@
+use std::fmt;
 use ast;
 
+/// A span of chars in the input, from the first char up to but not including the last one
+pub type Span = (usize, usize);
+
 #[derive(Debug, PartialEq)]
 pub struct Token {
     pub val: TokVal,
     pub span: (usize, usize),
 }
 
+impl fmt::Display for Token {
+    /// Shows the token along with its span, e.g. `Op(Plus) @ 2..3`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.val {
+            TokVal::Num(num) => try!(write!(f, "Num({})", num)),
+            ref val => try!(write!(f, "{:?}", val)),
+        }
+        write!(f, " @ {}..{}", self.span.0, self.span.1)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokVal {
     Name(String),
@@ -13,7 +28,9 @@ pub enum TokVal {
     Op(OpKind),
     OpenDelim(DelimKind),
     CloseDelim(DelimKind),
-    AbsDelim
+    AbsDelim,
+    Comma,
+    Semicolon,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -24,7 +41,17 @@ pub enum OpKind {
     Div,
     Pow,
     Fact,
+    Deg,
     Assign,
+    And,
+    Or,
+    Xor,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+    Equal,
+    NotEqual,
 }
 
 impl Into<ast::OpKind> for OpKind {
@@ -36,16 +63,63 @@ impl Into<ast::OpKind> for OpKind {
             OpKind::Div => ast::OpKind::Div,
             OpKind::Pow => ast::OpKind::Pow,
             OpKind::Fact => ast::OpKind::Fact,
+            OpKind::Deg => ast::OpKind::Deg,
             OpKind::Assign => ast::OpKind::Assign,
+            OpKind::And => ast::OpKind::And,
+            OpKind::Or => ast::OpKind::Or,
+            OpKind::Xor => ast::OpKind::Xor,
+            OpKind::Less => ast::OpKind::Less,
+            OpKind::Greater => ast::OpKind::Greater,
+            OpKind::LessEq => ast::OpKind::LessEq,
+            OpKind::GreaterEq => ast::OpKind::GreaterEq,
+            OpKind::Equal => ast::OpKind::Equal,
+            OpKind::NotEqual => ast::OpKind::NotEqual,
         }
     }
 }
 
+/// A coarse classification of tokens, e.g. for syntax highlighting
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TokenCategory {
+    Number,
+    Operator,
+    Function,
+    Constant,
+    Delimiter,
+    Name,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum DelimKind {
     Paren,
     Bracket,
     Brace,
+    /// `⌊x⌋`, which rounds `x` down rather than just grouping it
+    Floor,
+    /// `⌈x⌉`, which rounds `x` up rather than just grouping it
+    Ceil,
+}
+
+impl DelimKind {
+    pub fn open_char(&self) -> char {
+        match *self {
+            DelimKind::Paren => '(',
+            DelimKind::Bracket => '[',
+            DelimKind::Brace => '{',
+            DelimKind::Floor => '⌊',
+            DelimKind::Ceil => '⌈',
+        }
+    }
+
+    pub fn close_char(&self) -> char {
+        match *self {
+            DelimKind::Paren => ')',
+            DelimKind::Bracket => ']',
+            DelimKind::Brace => '}',
+            DelimKind::Floor => '⌋',
+            DelimKind::Ceil => '⌉',
+        }
+    }
 }
 
 impl TokVal {
@@ -57,6 +131,39 @@ impl TokVal {
         }
     }
 
+    pub fn is_num(&self) -> bool {
+        if let TokVal::Num(_) = *self {
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_name(&self) -> bool {
+        if let TokVal::Name(_) = *self {
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Describes the token for error messages, e.g. as operator `+` or number 30
+    pub fn describe(&self) -> String {
+        match *self {
+            TokVal::Name(ref name) => format!("name `{}`", name),
+            TokVal::Num(num) => format!("number {}", num),
+            TokVal::Op(ref op) => {
+                let op: ast::OpKind = op.clone().into();
+                format!("operator `{}`", op.symbol())
+            },
+            TokVal::OpenDelim(ref kind) => format!("`{}`", kind.open_char()),
+            TokVal::CloseDelim(ref kind) => format!("`{}`", kind.close_char()),
+            TokVal::AbsDelim => "`|`".to_string(),
+            TokVal::Comma => "`,`".to_string(),
+            TokVal::Semicolon => "`;`".to_string(),
+        }
+    }
+
     pub fn is_open_delim(&self) -> bool {
         if let TokVal::OpenDelim(_) = *self {
             true
@@ -65,6 +172,15 @@ impl TokVal {
         }
     }
 
+    /// Whether this is an opening delimiter which can surround the arguments of a function
+    pub fn opens_arguments(&self) -> bool {
+        match *self {
+            TokVal::OpenDelim(DelimKind::Floor) | TokVal::OpenDelim(DelimKind::Ceil) => false,
+            TokVal::OpenDelim(_) => true,
+            _ => false,
+        }
+    }
+
     pub fn is_close_delim(&self) -> bool {
         if let TokVal::CloseDelim(_) = *self {
             true